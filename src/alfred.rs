@@ -3,10 +3,12 @@
 //!
 //! Defines:
 //! - AlfredItem, Icon, Mods, ModifierAction, Text, AlfredResponse
-//! - `From<&SearchResult> for AlfredItem`
-//! - `output_results` to serialize and print the Alfred JSON response.
+//! - `From<&SearchResult> for AlfredItem` and `TryFrom<&TabTarget> for AlfredItem`
+//! - `output_results` / `output_tabs` to serialize and print the Alfred
+//!   JSON response.
 
 use crate::search::SearchResult;
+use crate::tabs::TabTarget;
 use crate::utils;
 use serde::Serialize;
 use std::error::Error;
@@ -80,6 +82,36 @@ pub fn output_results(items: &[SearchResult]) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Output open tabs to Alfred, each passing itself on to the `focus` command
+pub fn output_tabs(targets: &[TabTarget]) -> Result<(), Box<dyn Error>> {
+    let items = targets
+        .iter()
+        .map(AlfredItem::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!("{}", serde_json::to_string(&AlfredResponse { items })?);
+    Ok(())
+}
+
+/// Convert an open tab to an AlfredItem whose argument is the tab itself
+impl TryFrom<&TabTarget> for AlfredItem {
+    type Error = serde_json::Error;
+
+    fn try_from(target: &TabTarget) -> Result<Self, Self::Error> {
+        Ok(AlfredItem {
+            uid: None,
+            title: target.tab.title.clone(),
+            subtitle: Some(format!("{} · {}", target.tab.subtitle, target.browser)),
+            arg: Some(serde_json::to_string(target)?),
+            icon: None,
+            valid: Some(true),
+            mods: None,
+            text: None,
+            quicklookurl: Some(target.tab.url.clone()),
+        })
+    }
+}
+
 /// Convert a SearchResult to an AlfredItem
 impl From<&SearchResult> for AlfredItem {
     fn from(result: &SearchResult) -> Self {
@@ -94,10 +126,20 @@ impl From<&SearchResult> for AlfredItem {
             None
         };
 
+        // Name the browser profile the result came from
+        let mut subtitle = match result.origin() {
+            Some(origin) => format!("{} · {}", result.subtitle, origin),
+            None => result.subtitle.clone(),
+        };
+        // Salvaged results may be incomplete or stale
+        if result.recovered {
+            subtitle.push_str(" · recovered");
+        }
+
         AlfredItem {
             uid: Some(result.url.clone()),
             title: result.title.clone(),
-            subtitle: Some(result.subtitle.clone()),
+            subtitle: Some(subtitle),
             arg: Some(result.url.clone()),
             icon: favicon,
             valid: Some(true),
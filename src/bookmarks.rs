@@ -1,59 +1,50 @@
 //! Searches bookmarks across all enabled browsers.
 //!
 //! Provides:
-//! - `search(query: &str)` entry point
-//! - `search_chrome_bookmarks` / `search_safari_bookmarks`
+//! - `search(query: &Query)` entry point, reading every `BrowserSource`
+//!   in parallel through `source::read_profiles`, which applies the
+//!   query's filters
+//! - `search_chrome_bookmarks` / `search_safari_bookmarks` /
+//!   `search_qutebrowser_bookmarks` / `search_epiphany_bookmarks` /
+//!   `search_falkon_bookmarks`, plus `search_arc_sidebar` for Arc's
+//!   spaces, pinned tabs and favorites
+//! - `FIREFOX_BOOKMARKS` listing the `places.sqlite` queries by schema
+//!   version, found by engine through `queries_for`
 //! - Recursive extractors (`extract_chrome_bookmarks`,
-//!   `extract_safari_bookmarks`)
-//! - Uses serde_json and plist for parsing, rayon for parallelism,
-//!   and filter_results to match the query.
+//!   `extract_safari_bookmarks`, `extract_falkon_bookmarks`,
+//!   `extract_arc_items`, and `extract_firefox_backup` for the mozlz4
+//!   bookmark backups read when `places.sqlite` cannot be)
+//! - Uses serde_json, plist and the GVDB reader for parsing, and
+//!   filter_results to match the query.
 
-use crate::browser::get_available_browsers;
-use crate::db::{create_temp_db_copy, query_firefox_bookmarks};
+use crate::browser::Engine;
+use crate::db::{open_browser_db, query_with_schema, Pushdown, QuerySet, QueryVariant};
+use crate::gvdb::{self, Item, Table};
+use crate::mozlz4;
+use crate::query::Query;
 use crate::search::{filter_results, ResultSource, SearchResult};
+use crate::source::{read_profiles, sources};
 use plist::Value as PlistValue;
-use rayon::prelude::*;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
 /// Search bookmarks across all enabled browsers
-pub fn search(query: &str) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+pub fn search(query: &Query) -> Result<Vec<SearchResult>, Box<dyn Error>> {
     log::trace!("Beginning bookmarks search");
-    let browsers = get_available_browsers();
-
-    // Perform searches in parallel using rayon
-    let browser_results: Vec<Vec<SearchResult>> = browsers
-        .par_iter()
-        .filter_map(|(browser, paths)| {
-            if let Some(bookmarks_path) = &paths.bookmarks {
-                let result = match browser {
-                    b if b.is_safari_like() => search_safari_bookmarks(bookmarks_path, query),
-                    b if b.is_firefox_like() => search_firefox_bookmarks(bookmarks_path),
-                    b if b.is_chrome_like() => search_chrome_bookmarks(bookmarks_path, query),
-                    _ => unreachable!("unsupported browser: {:?}", browser),
-                };
-
-                match result {
-                    Ok(results) => Some(results),
-                    Err(e) => {
-                        log::error!("Error searching {:?} bookmarks: {}", browser, e);
-                        None
-                    }
-                }
-            } else {
-                None
-            }
-        })
-        .collect();
+    let sources = sources();
 
-    // Collect results
-    let mut all_results = Vec::new();
-    for results in browser_results {
-        all_results.extend(results);
-    }
+    // Read every profile in parallel, keeping what passes the filters
+    let mut all_results = read_profiles(
+        &sources,
+        query,
+        "bookmarks",
+        |capabilities| capabilities.bookmarks,
+        |source, profile| source.bookmarks(profile, query),
+    );
 
     // Deduplicate by URL
     let mut seen = std::collections::HashSet::new();
@@ -66,7 +57,7 @@ pub fn search(query: &str) -> Result<Vec<SearchResult>, Box<dyn Error>> {
 }
 
 /// Search Chrome-based browser bookmarks
-fn search_chrome_bookmarks(
+pub fn search_chrome_bookmarks(
     bookmark_path: &Path,
     query: &str,
 ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
@@ -105,6 +96,10 @@ fn extract_chrome_bookmarks(value: &Value, results: &mut Vec<SearchResult>) {
                     source: ResultSource::Bookmark,
                     visit_count: None,
                     last_visit: None,
+                    profile: None,
+                    browser: None,
+                    recovered: false,
+                    source_index: None,
                 });
             }
         }
@@ -128,7 +123,7 @@ fn extract_chrome_bookmarks(value: &Value, results: &mut Vec<SearchResult>) {
 }
 
 /// Search Safari bookmarks
-fn search_safari_bookmarks(
+pub fn search_safari_bookmarks(
     bookmark_path: &Path,
     query: &str,
 ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
@@ -162,6 +157,10 @@ fn extract_safari_bookmarks(value: &PlistValue, results: &mut Vec<SearchResult>)
                         source: ResultSource::Bookmark,
                         visit_count: None,
                         last_visit: None,
+                        profile: None,
+                        browser: None,
+                        recovered: false,
+                        source_index: None,
                     });
                 }
             }
@@ -187,36 +186,556 @@ fn extract_safari_bookmarks(value: &PlistValue, results: &mut Vec<SearchResult>)
     }
 }
 
-/// Firefox bookmarks (SQLite)
-fn search_firefox_bookmarks(bookmark_path: &Path) -> Result<Vec<SearchResult>, Box<dyn Error>> {
-    // Copy the locked db for easy access
-    let (_tmp, conn) = create_temp_db_copy(bookmark_path, None, None)?;
-
+/// Firefox `places.sqlite` bookmark queries, by `user_version`
+pub const FIREFOX_BOOKMARKS: QuerySet = QuerySet {
     // grab every “real” bookmark (type=1) and where the data isn't sparse.
-    let sql = r#"
-        SELECT b.title, p.url
+    variants: &[
+        // Schema 11 added `guid`, by which the tags root is known; entries
+        // under a tag folder are the bookmark tagged again, so they are
+        // skipped, and an untitled bookmark shows its page's title
+        QueryVariant {
+            name: "firefox",
+            versions: 11..=i64::MAX,
+            sql: r#"
+            SELECT COALESCE(NULLIF(b.title, ''), p.title) AS title, p.url
+              FROM moz_bookmarks AS b
+              JOIN moz_places   AS p ON b.fk = p.id
+             WHERE b.type = 1
+               AND p.url IS NOT NULL
+               AND COALESCE(NULLIF(b.title, ''), p.title) IS NOT NULL
+               AND NOT EXISTS (
+                   SELECT 1
+                     FROM moz_bookmarks AS tag
+                     JOIN moz_bookmarks AS tags ON tag.parent = tags.id
+                    WHERE tag.id = b.parent
+                      AND tags.guid = 'tags________'
+               )
+        "#,
+        },
+        QueryVariant {
+            name: "firefox-10",
+            versions: 7..=10,
+            sql: r#"
+            SELECT b.title, p.url
+              FROM moz_bookmarks AS b
+              JOIN moz_places   AS p ON b.fk = p.id
+             WHERE b.type = 1
+               AND p.url   IS NOT NULL
+               AND b.title IS NOT NULL
+        "#,
+        },
+    ],
+    // Folders and separators have no place, so the join still skips them
+    minimal: "SELECT b.title, p.url
           FROM moz_bookmarks AS b
-          JOIN moz_places   AS p ON b.fk = p.id
-         WHERE b.type = 1
-           AND p.url   IS NOT NULL
-           AND b.title IS NOT NULL
-    "#;
+          JOIN moz_places AS p ON b.fk = p.id
+         WHERE b.title IS NOT NULL",
+    salvage: None,
+};
+
+/// Bookmark queries chosen by schema version for an engine, if it has any
+pub fn queries_for(engine: Engine) -> Option<&'static QuerySet> {
+    match engine {
+        Engine::Gecko => Some(&FIREFOX_BOOKMARKS),
+        _ => None,
+    }
+}
+
+/// Firefox bookmarks (SQLite)
+pub fn search_firefox_bookmarks(
+    bookmark_path: &Path,
+    query: &str,
+    locked: Option<bool>,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let raw = match read_firefox_bookmarks(bookmark_path, locked) {
+        Ok(raw) => raw,
+        Err(e) => {
+            // Firefox backs its bookmarks up daily next to `places.sqlite`
+            let Some(profile_dir) = bookmark_path.parent() else {
+                return Err(e);
+            };
+            match read_firefox_bookmark_backup(profile_dir) {
+                Ok(raw) => {
+                    log::warn!(
+                        "Reading the bookmark backup of {:?}, as it cannot be read: {}",
+                        bookmark_path,
+                        e
+                    );
+                    raw
+                }
+                Err(_) => return Err(e),
+            }
+        }
+    };
+
+    Ok(filter_results(raw, query))
+}
+
+/// Read the bookmarks of `places.sqlite`
+fn read_firefox_bookmarks(
+    bookmark_path: &Path,
+    locked: Option<bool>,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    // Copy the locked db for easy access
+    let (_tmp, conn) = open_browser_db(bookmark_path, locked)?;
 
     // Query the firefox bookmarks
-    let raw: Vec<SearchResult> = query_firefox_bookmarks(&conn, sql, |row| {
-        let title: String = row.get(0)?;
-        let url: String = row.get(1)?;
-        Ok(SearchResult {
-            title: title.clone(),
-            url: url.clone(),
-            subtitle: url,
+    let raw: Vec<SearchResult> =
+        query_with_schema(&conn, &FIREFOX_BOOKMARKS, &Pushdown::default(), |row| {
+            let title: String = row.get(0)?;
+            let url: String = row.get(1)?;
+            Ok(SearchResult {
+                title: title.clone(),
+                url: url.clone(),
+                subtitle: url,
+                favicon: None,
+                source: ResultSource::Bookmark,
+                visit_count: None,
+                last_visit: None,
+                profile: None,
+                browser: None,
+                recovered: false,
+                source_index: None,
+            })
+        })?;
+
+    Ok(raw)
+}
+
+/// Read the latest backup in a Firefox profile's `bookmarkbackups`.
+///
+/// Backups are mozlz4 compressed JSON, named after the day they were
+/// taken (`bookmarks-2024-05-01_...jsonlz4`), so the last name is the
+/// latest.
+fn read_firefox_bookmark_backup(profile_dir: &Path) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let latest = std::fs::read_dir(profile_dir.join("bookmarkbackups"))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.to_string_lossy().ends_with(".jsonlz4"))
+        .max()
+        .ok_or("No bookmark backup")?;
+
+    let json = mozlz4::decompress(&std::fs::read(&latest)?)
+        .map_err(|e| format!("{}: {}", latest.display(), e))?;
+    let root: Value = serde_json::from_slice(&json)?;
+
+    let mut results = Vec::new();
+    extract_firefox_backup(&root, &mut results);
+    Ok(results)
+}
+
+/// Recursively extract bookmarks from a Firefox bookmark backup
+fn extract_firefox_backup(node: &Value, results: &mut Vec<SearchResult>) {
+    // Queries such as "Most Visited" are `place:` URIs, not pages
+    if let (Some("text/x-moz-place"), Some(url)) = (
+        node.get("type").and_then(Value::as_str),
+        node.get("uri").and_then(Value::as_str),
+    ) {
+        if !url.starts_with("place:") {
+            let title = node
+                .get("title")
+                .and_then(Value::as_str)
+                .filter(|title| !title.is_empty())
+                .unwrap_or(url);
+            results.push(SearchResult {
+                title: title.to_string(),
+                url: url.to_string(),
+                subtitle: url.to_string(),
+                favicon: None,
+                source: ResultSource::Bookmark,
+                visit_count: None,
+                last_visit: None,
+                profile: None,
+                browser: None,
+                recovered: false,
+                source_index: None,
+            });
+        }
+    }
+
+    if let Some(Value::Array(children)) = node.get("children") {
+        for child in children {
+            extract_firefox_backup(child, results);
+        }
+    }
+}
+
+/// Search qutebrowser bookmarks and quickmarks, given its config directory
+pub fn search_qutebrowser_bookmarks(
+    config_dir: &Path,
+    query: &str,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let mut results = Vec::new();
+
+    // `bookmarks/urls` holds one `URL TITLE` pair per line
+    if let Ok(contents) = std::fs::read_to_string(config_dir.join("bookmarks").join("urls")) {
+        results.extend(contents.lines().filter_map(|line| {
+            let (url, title) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            parse_qutebrowser_mark(url, title)
+        }));
+    }
+
+    // `quickmarks` holds `NAME URL` pairs, where the name may contain spaces
+    if let Ok(contents) = std::fs::read_to_string(config_dir.join("quickmarks")) {
+        results.extend(contents.lines().filter_map(|line| {
+            let (name, url) = line.trim().rsplit_once(' ')?;
+            parse_qutebrowser_mark(url, name)
+        }));
+    }
+
+    // Search bookmarks
+    let matching_results = filter_results(results, query);
+    Ok(matching_results)
+}
+
+/// Build a bookmark result from one qutebrowser bookmark or quickmark line
+fn parse_qutebrowser_mark(url: &str, title: &str) -> Option<SearchResult> {
+    let url = url.trim();
+    if url.is_empty() {
+        return None;
+    }
+
+    // Untitled bookmarks are shown by their URL
+    let title = match title.trim() {
+        "" => url,
+        title => title,
+    };
+
+    Some(SearchResult {
+        title: title.to_string(),
+        url: url.to_string(),
+        subtitle: url.to_string(),
+        favicon: None,
+        source: ResultSource::Bookmark,
+        visit_count: None,
+        last_visit: None,
+        profile: None,
+        browser: None,
+        recovered: false,
+        source_index: None,
+    })
+}
+
+/// Search GNOME Web (Epiphany) bookmarks stored in `bookmarks.gvdb`
+pub fn search_epiphany_bookmarks(
+    bookmark_path: &Path,
+    query: &str,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let data = std::fs::read(bookmark_path)?;
+    let root = Table::root(&data)?;
+
+    // Bookmarks are keyed by URL in the nested `bookmarks` table
+    let bookmarks = match root.get("bookmarks")? {
+        Some(Item::Table(table)) => table,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut results = Vec::new();
+    for (url, item) in bookmarks.entries()? {
+        if let Item::Value(value) = item {
+            match gvdb::parse_variant(value) {
+                Ok(value) => results.push(extract_epiphany_bookmark(url, &value)),
+                Err(e) => log::warn!("Skipping Epiphany bookmark {}: {}", url, e),
+            }
+        }
+    }
+
+    // Search bookmarks
+    let matching_results = filter_results(results, query);
+    Ok(matching_results)
+}
+
+/// Build a bookmark from its `(x title id modified uploaded tags)` tuple.
+/// Fields are picked by type so older layouts of the tuple work too.
+fn extract_epiphany_bookmark(url: String, value: &gvdb::Value) -> SearchResult {
+    let fields = match value {
+        gvdb::Value::Tuple(fields) => fields.as_slice(),
+        _ => &[],
+    };
+
+    let title = fields
+        .iter()
+        .find_map(gvdb::Value::as_str)
+        .filter(|title| !title.is_empty())
+        .unwrap_or(&url)
+        .to_string();
+    let tags: Vec<&str> = fields
+        .iter()
+        .find_map(gvdb::Value::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(gvdb::Value::as_str)
+        .collect();
+
+    // Show tags next to the URL so they are matched by the query as well
+    let subtitle = if tags.is_empty() {
+        url.clone()
+    } else {
+        format!("{} [{}]", url, tags.join(", "))
+    };
+
+    SearchResult {
+        title,
+        url,
+        subtitle,
+        favicon: None,
+        source: ResultSource::Bookmark,
+        visit_count: None,
+        last_visit: None,
+        profile: None,
+        browser: None,
+        recovered: false,
+        source_index: None,
+    }
+}
+
+/// Search Falkon bookmarks
+pub fn search_falkon_bookmarks(
+    bookmark_path: &Path,
+    query: &str,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(bookmark_path)?;
+    let bookmarks: Value = serde_json::from_str(&contents)?;
+
+    // Each root (toolbar, menu, unsorted...) is a folder of its own
+    let mut results = Vec::new();
+    if let Some(Value::Object(roots)) = bookmarks.get("roots") {
+        for root in roots.values() {
+            extract_falkon_bookmarks(root, &[], &mut results);
+        }
+    }
+
+    // Search bookmarks
+    let matching_results = filter_results(results, query);
+    Ok(matching_results)
+}
+
+/// Recursively extract bookmarks from Falkon's folder tree, keeping the folder path
+fn extract_falkon_bookmarks(value: &Value, folders: &[&str], results: &mut Vec<SearchResult>) {
+    let Some(obj) = value.as_object() else {
+        return;
+    };
+    let name = obj.get("name").and_then(Value::as_str).unwrap_or_default();
+
+    // Check if this is a bookmark
+    if let (Some("url"), Some(url)) = (
+        obj.get("type").and_then(Value::as_str),
+        obj.get("url").and_then(Value::as_str),
+    ) {
+        let title = if name.is_empty() { url } else { name };
+        results.push(SearchResult {
+            title: title.to_string(),
+            url: url.to_string(),
+            subtitle: if folders.is_empty() {
+                url.to_string()
+            } else {
+                format!("{} [{}]", url, folders.join("/"))
+            },
             favicon: None,
             source: ResultSource::Bookmark,
             visit_count: None,
             last_visit: None,
-        })
-    })?;
+            profile: None,
+            browser: None,
+            recovered: false,
+            source_index: None,
+        });
+    }
 
-    // finally apply your existing filter_results
-    Ok(raw)
+    // Check for children (folders)
+    if let Some(Value::Array(children)) = obj.get("children") {
+        let mut path = folders.to_vec();
+        if !name.is_empty() {
+            path.push(name);
+        }
+        for child in children {
+            extract_falkon_bookmarks(child, &path, results);
+        }
+    }
+}
+
+/// Search the Arc sidebar (`StorableSidebar.json`) for the spaces of one profile.
+///
+/// Pinned tabs, their folders and the favorites row live in the sidebar
+/// rather than in `Bookmarks`. Each space belongs to a profile, named by its
+/// directory (`Default`, `Profile 1`, …), and keeps its pinned items in the
+/// container following `"pinned"` in its `containerIDs`.
+pub fn search_arc_sidebar(
+    sidebar_path: &Path,
+    profile_dir: &str,
+    query: &str,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let mut file = File::open(sidebar_path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let sidebar: Value = serde_json::from_str(&contents)?;
+
+    // The containers list mixes a "global" entry with the one holding the items
+    let Some(container) = sidebar
+        .pointer("/sidebar/containers")
+        .and_then(Value::as_array)
+        .and_then(|containers| containers.iter().find(|c| c.get("items").is_some()))
+    else {
+        return Ok(Vec::new());
+    };
+
+    // Lists alternate between ids and the objects they name
+    let objects = |key: &str| -> Vec<&Value> {
+        container
+            .get(key)
+            .and_then(Value::as_array)
+            .map(|list| list.iter().filter(|v| v.is_object()).collect())
+            .unwrap_or_default()
+    };
+    let items: HashMap<&str, &Value> = objects("items")
+        .into_iter()
+        .filter_map(|item| Some((item.get("id")?.as_str()?, item)))
+        .collect();
+
+    let mut results = Vec::new();
+
+    // Favorites: each container id follows the profile it belongs to
+    let mut owner = None;
+    for entry in container
+        .get("topAppsContainerIDs")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        match entry.as_str() {
+            Some(id) if is_arc_profile(owner, profile_dir) => {
+                extract_arc_items(&items, id, &["Favorites"], &mut results)
+            }
+            Some(_) => {}
+            None => owner = Some(entry),
+        }
+    }
+
+    for space in objects("spaces") {
+        if !is_arc_profile(space.get("profile"), profile_dir) {
+            continue;
+        }
+        let title = space
+            .get("title")
+            .and_then(Value::as_str)
+            .unwrap_or("Space");
+
+        // `containerIDs` alternates a kind and an id: ["unpinned", id, "pinned", id]
+        let ids: Vec<&str> = space
+            .get("containerIDs")
+            .and_then(Value::as_array)
+            .map(|ids| ids.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        for pair in ids.windows(2).filter(|pair| pair[0] == "pinned") {
+            extract_arc_items(&items, pair[1], &[title], &mut results);
+        }
+    }
+
+    Ok(filter_results(results, query))
+}
+
+/// Check whether a sidebar `profile` entry names the given profile directory.
+/// Entries without a custom directory belong to the default profile.
+fn is_arc_profile(profile: Option<&Value>, profile_dir: &str) -> bool {
+    match profile
+        .and_then(|profile| profile.pointer("/custom/_0/directoryBasename"))
+        .and_then(Value::as_str)
+    {
+        Some(dir) => dir == profile_dir,
+        None => profile_dir == "Default",
+    }
+}
+
+/// Recursively extract the tabs under a sidebar item, tracking folder names
+fn extract_arc_items(
+    items: &HashMap<&str, &Value>,
+    id: &str,
+    folders: &[&str],
+    results: &mut Vec<SearchResult>,
+) {
+    let Some(item) = items.get(id) else {
+        return;
+    };
+    let title = item
+        .get("title")
+        .and_then(Value::as_str)
+        .filter(|title| !title.is_empty());
+
+    // Check if this is a tab
+    if let Some(tab) = item.pointer("/data/tab") {
+        let Some(url) = tab.get("savedURL").and_then(Value::as_str) else {
+            return;
+        };
+        let title = title
+            .or_else(|| tab.get("savedTitle").and_then(Value::as_str))
+            .unwrap_or(url);
+
+        results.push(SearchResult {
+            title: title.to_string(),
+            url: url.to_string(),
+            subtitle: format!("{} [{}]", url, folders.join("/")),
+            favicon: None,
+            source: ResultSource::Bookmark,
+            visit_count: None,
+            last_visit: None,
+            profile: None,
+            browser: None,
+            recovered: false,
+            source_index: None,
+        });
+        return;
+    }
+
+    // Folders add their name to the path, containers do not
+    let mut path = folders.to_vec();
+    if let (Some(title), Some(_)) = (title, item.pointer("/data/list")) {
+        path.push(title);
+    }
+    for child in item
+        .get("childrenIds")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+    {
+        extract_arc_items(items, child, &path, results);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_places_fall_back_to_the_latest_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = dir.path().join("bookmarkbackups");
+        std::fs::create_dir(&backups).unwrap();
+        let backup = |url: &str| {
+            let json = serde_json::json!({
+                "type": "text/x-moz-place-container",
+                "children": [
+                    {"type": "text/x-moz-place", "title": "Rust", "uri": url},
+                    {"type": "text/x-moz-place", "title": "Most Visited", "uri": "place:sort=8"},
+                ],
+            });
+            mozlz4::compress(&serde_json::to_vec(&json).unwrap()).unwrap()
+        };
+        std::fs::write(
+            backups.join("bookmarks-2024-01-01_2_abc.jsonlz4"),
+            backup("https://old.example"),
+        )
+        .unwrap();
+        std::fs::write(
+            backups.join("bookmarks-2024-05-01_2_def.jsonlz4"),
+            backup("https://www.rust-lang.org"),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("places.sqlite"), b"not a database").unwrap();
+
+        let results =
+            search_firefox_bookmarks(&dir.path().join("places.sqlite"), "", Some(false)).unwrap();
+        let urls: Vec<&str> = results.iter().map(|result| result.url.as_str()).collect();
+        assert_eq!(urls, ["https://www.rust-lang.org"]);
+    }
 }
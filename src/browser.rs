@@ -1,15 +1,47 @@
 //! Discovers installed browsers and their history/bookmarks file paths.
 //!
 //! Defines:
-//! - `Browser` enum with variants for supported browsers
-//! - `BrowserPaths` struct holding optional history/bookmarks paths
-//! - `get_available_browsers` that reads HOME and environment flags
-//!   to return only enabled & existing browser paths.
+//! - `Browser` enum with variants for supported browsers, plus
+//!   `Browser::Custom` for those declared in the config file
+//! - `Engine` families used to pick the data readers
+//! - `Platform` / `Anchor` and the per-platform data directory tables,
+//!   honouring `XDG_CONFIG_HOME` on Linux
+//! - `Install` flavours, probing Flatpak and Snap sandboxes on Linux
+//! - `BrowserPaths` struct holding every profile found for a browser
+//! - `DataDir` / `probed_dirs` listing every directory discovery looks at
+//! - `mark_locked_profiles` flagging profiles a running browser holds open
+//! - `launched_profiles` for directories browsers were started with
+//!   (`--user-data-dir`, `-profile`), see `registry::custom_profile_dirs`
+//! - `get_available_browsers` that reads HOME (or the root set through
+//!   `set_home_root`) and environment flags to return only enabled &
+//!   existing browser profiles.
 
+use crate::config;
+use crate::profiles::{
+    chromium_profiles, falkon_profiles, gecko_profile, gecko_profiles, is_profile_selected,
+    qutebrowser_profile, Profile,
+};
+use crate::registry;
+use crate::running;
 use dirs::home_dir;
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Directory searched instead of HOME, see `set_home_root`
+static HOME_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Search the given directory (an exported home, backup or unpacked
+/// archive) instead of the user's home
+pub fn set_home_root(path: PathBuf) {
+    let _ = HOME_ROOT.set(path);
+}
+
+/// Returns true if another root is searched instead of HOME
+pub fn has_home_root() -> bool {
+    HOME_ROOT.get().is_some()
+}
 
 /// Supported browser types
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -27,34 +59,122 @@ pub enum Browser {
     Arc,
     Chromium,
     Sidekick,
+    ChromeDev,
+    ChromeCanary,
+    BraveNightly,
+    EdgeBeta,
+    EdgeDev,
+    EdgeCanary,
+    OperaGx,
+    VivaldiSnapshot,
+    Thorium,
+    UngoogledChromium,
+    Yandex,
+    Helium,
+    LibreWolf,
+    Floorp,
+    Waterfox,
+    Mullvad,
+    Qutebrowser,
+    Epiphany,
+    Falkon,
+    /// A browser declared in the config file, by its index there
+    Custom(usize),
+}
+
+/// Browser engine families, which decide how data files are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    Chromium,
+    Gecko,
+    WebKit,
+    Qutebrowser,
+    Epiphany,
+    Falkon,
 }
 
 impl Browser {
-    /// Returns true if this browser is based on Chromium.
-    pub fn is_chrome_like(&self) -> bool {
-        matches!(
-            self,
-            Browser::Chrome
-                | Browser::ChromeBeta
-                | Browser::Brave
-                | Browser::BraveBeta
-                | Browser::Edge
-                | Browser::Opera
-                | Browser::Vivaldi
-                | Browser::Arc
-                | Browser::Chromium
-                | Browser::Sidekick
-        )
-    }
+    /// Every browser known without the config file
+    pub const BUILT_IN: [Browser; 32] = [
+        Browser::Chrome,
+        Browser::ChromeBeta,
+        Browser::Brave,
+        Browser::BraveBeta,
+        Browser::Safari,
+        Browser::Firefox,
+        Browser::Edge,
+        Browser::Zen,
+        Browser::Opera,
+        Browser::Vivaldi,
+        Browser::Arc,
+        Browser::Chromium,
+        Browser::Sidekick,
+        Browser::ChromeDev,
+        Browser::ChromeCanary,
+        Browser::BraveNightly,
+        Browser::EdgeBeta,
+        Browser::EdgeDev,
+        Browser::EdgeCanary,
+        Browser::OperaGx,
+        Browser::VivaldiSnapshot,
+        Browser::Thorium,
+        Browser::UngoogledChromium,
+        Browser::Yandex,
+        Browser::Helium,
+        Browser::LibreWolf,
+        Browser::Floorp,
+        Browser::Waterfox,
+        Browser::Mullvad,
+        Browser::Qutebrowser,
+        Browser::Epiphany,
+        Browser::Falkon,
+    ];
 
-    /// Returns true if this browser is based on Firefox.
-    pub fn is_firefox_like(&self) -> bool {
-        matches!(self, Browser::Firefox | Browser::Zen)
+    /// Find a built-in browser by its environment variable name
+    pub fn from_env_var(var: &str) -> Option<Browser> {
+        Browser::BUILT_IN
+            .into_iter()
+            .find(|browser| browser.env_var() == var)
     }
 
-    /// Returns true if this browser is based on WebKit.
-    pub fn is_safari_like(&self) -> bool {
-        matches!(self, Browser::Safari)
+    /// Get the engine family this browser is built on
+    pub fn engine(&self) -> Engine {
+        match self {
+            Browser::Chrome
+            | Browser::ChromeBeta
+            | Browser::Brave
+            | Browser::BraveBeta
+            | Browser::Edge
+            | Browser::Opera
+            | Browser::Vivaldi
+            | Browser::Arc
+            | Browser::Chromium
+            | Browser::Sidekick
+            | Browser::ChromeDev
+            | Browser::ChromeCanary
+            | Browser::BraveNightly
+            | Browser::EdgeBeta
+            | Browser::EdgeDev
+            | Browser::EdgeCanary
+            | Browser::OperaGx
+            | Browser::VivaldiSnapshot
+            | Browser::Thorium
+            | Browser::UngoogledChromium
+            | Browser::Yandex
+            | Browser::Helium => Engine::Chromium,
+            Browser::Firefox
+            | Browser::Zen
+            | Browser::LibreWolf
+            | Browser::Floorp
+            | Browser::Waterfox
+            | Browser::Mullvad => Engine::Gecko,
+            Browser::Safari => Engine::WebKit,
+            Browser::Qutebrowser => Engine::Qutebrowser,
+            Browser::Epiphany => Engine::Epiphany,
+            Browser::Falkon => Engine::Falkon,
+            Browser::Custom(index) => config::custom_browser(*index).engine,
+        }
     }
 
     /// Get the display name of the browser
@@ -73,6 +193,26 @@ impl Browser {
             Browser::Arc => "Arc",
             Browser::Chromium => "Chromium",
             Browser::Sidekick => "Sidekick",
+            Browser::ChromeDev => "Google Chrome Dev",
+            Browser::ChromeCanary => "Google Chrome Canary",
+            Browser::BraveNightly => "Brave Nightly",
+            Browser::EdgeBeta => "Microsoft Edge Beta",
+            Browser::EdgeDev => "Microsoft Edge Dev",
+            Browser::EdgeCanary => "Microsoft Edge Canary",
+            Browser::OperaGx => "Opera GX",
+            Browser::VivaldiSnapshot => "Vivaldi Snapshot",
+            Browser::Thorium => "Thorium",
+            Browser::UngoogledChromium => "Ungoogled Chromium",
+            Browser::Yandex => "Yandex Browser",
+            Browser::Helium => "Helium",
+            Browser::LibreWolf => "LibreWolf",
+            Browser::Floorp => "Floorp",
+            Browser::Waterfox => "Waterfox",
+            Browser::Mullvad => "Mullvad Browser",
+            Browser::Qutebrowser => "qutebrowser",
+            Browser::Epiphany => "GNOME Web",
+            Browser::Falkon => "Falkon",
+            Browser::Custom(index) => &config::custom_browser(*index).name,
         }
     }
 
@@ -92,146 +232,832 @@ impl Browser {
             Browser::Arc => "arc",
             Browser::Chromium => "chromium",
             Browser::Sidekick => "sidekick",
+            Browser::ChromeDev => "chrome_dev",
+            Browser::ChromeCanary => "chrome_canary",
+            Browser::BraveNightly => "brave_nightly",
+            Browser::EdgeBeta => "edge_beta",
+            Browser::EdgeDev => "edge_dev",
+            Browser::EdgeCanary => "edge_canary",
+            Browser::OperaGx => "opera_gx",
+            Browser::VivaldiSnapshot => "vivaldi_snapshot",
+            Browser::Thorium => "thorium",
+            Browser::UngoogledChromium => "ungoogled_chromium",
+            Browser::Yandex => "yandex",
+            Browser::Helium => "helium",
+            Browser::LibreWolf => "librewolf",
+            Browser::Floorp => "floorp",
+            Browser::Waterfox => "waterfox",
+            Browser::Mullvad => "mullvad_browser",
+            Browser::Qutebrowser => "qutebrowser",
+            Browser::Epiphany => "epiphany",
+            Browser::Falkon => "falkon",
+            Browser::Custom(index) => &config::custom_browser(*index).name,
         }
     }
 
-    /// Check if this browser is enabled in the workflow configuration
+    /// Executables the browser runs as on Linux. Entries with a `/` match the
+    /// end of the executable path, the others its file name.
+    pub fn process_names(&self) -> &'static [&'static str] {
+        match self {
+            Browser::Chrome => &["google/chrome/chrome", "google-chrome"],
+            Browser::ChromeBeta => &["chrome-beta/chrome"],
+            Browser::ChromeDev => &["chrome-unstable/chrome"],
+            Browser::Brave => &["brave.com/brave/brave", "brave-browser"],
+            Browser::BraveBeta => &["brave.com/brave-beta/brave"],
+            Browser::BraveNightly => &["brave.com/brave-nightly/brave"],
+            Browser::Edge => &["msedge/msedge"],
+            Browser::EdgeBeta => &["msedge-beta/msedge"],
+            Browser::EdgeDev => &["msedge-dev/msedge"],
+            Browser::Opera => &["opera/opera"],
+            Browser::Vivaldi => &["vivaldi/vivaldi-bin"],
+            Browser::VivaldiSnapshot => &["vivaldi-snapshot/vivaldi-bin"],
+            Browser::Chromium => &["chromium", "chromium-browser"],
+            Browser::Thorium => &["thorium", "thorium-browser"],
+            Browser::UngoogledChromium => &["ungoogled-chromium"],
+            Browser::Yandex => &["yandex_browser"],
+            Browser::Helium => &["helium"],
+            Browser::Firefox => &["firefox", "firefox-bin", "firefox-esr"],
+            Browser::Zen => &["zen", "zen-bin"],
+            Browser::LibreWolf => &["librewolf"],
+            Browser::Floorp => &["floorp"],
+            Browser::Waterfox => &["waterfox"],
+            Browser::Mullvad => &["mullvadbrowser", "mullvadbrowser.real"],
+            Browser::Qutebrowser => &["qutebrowser"],
+            Browser::Epiphany => &["epiphany", "epiphany-browser"],
+            Browser::Falkon => &["falkon"],
+            // macOS only, or unknown
+            Browser::Safari
+            | Browser::Arc
+            | Browser::Sidekick
+            | Browser::ChromeCanary
+            | Browser::EdgeCanary
+            | Browser::OperaGx
+            | Browser::Custom(_) => &[],
+        }
+    }
+
+    /// Check if this browser is enabled in the workflow configuration.
+    /// Custom browsers are enabled by their config entry instead.
     pub fn is_enabled(&self) -> bool {
-        crate::utils::get_env_bool(self.env_var())
+        match self {
+            Browser::Custom(index) => config::custom_browser(*index).enabled,
+            _ => crate::utils::get_env_bool(self.env_var()),
+        }
     }
 }
 
-/// Represents paths to browser data files
+/// Platforms with known browser data locations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    MacOs,
+    Linux,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 2] = [Platform::MacOs, Platform::Linux];
+
+    /// The platform this binary is running on, if browsers are known for it
+    pub fn current() -> Option<Platform> {
+        match std::env::consts::OS {
+            "macos" => Some(Platform::MacOs),
+            "linux" => Some(Platform::Linux),
+            _ => None,
+        }
+    }
+
+    /// Every browser data directory known for this platform, native installs first
+    pub fn data_dirs(&self) -> Vec<(Browser, Install, Anchor, &'static str)> {
+        let native = |table: &'static [(Browser, Anchor, &'static str)]| {
+            table
+                .iter()
+                .map(|&(browser, anchor, path)| (browser, Install::Native, anchor, path))
+        };
+
+        match self {
+            Platform::MacOs => native(MACOS_DATA_DIRS).collect(),
+            Platform::Linux => native(LINUX_DATA_DIRS)
+                .chain(
+                    LINUX_SANDBOXED_DATA_DIRS
+                        .iter()
+                        .map(|&(browser, install, path)| (browser, install, Anchor::Home, path)),
+                )
+                .collect(),
+        }
+    }
+}
+
+/// How a browser was installed, which decides where its data lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Install {
+    Native,
+    Flatpak,
+    Snap,
+}
+
+impl Install {
+    /// Get the display name of the install flavour
+    pub fn name(&self) -> &'static str {
+        match self {
+            Install::Native => "Native",
+            Install::Flatpak => "Flatpak",
+            Install::Snap => "Snap",
+        }
+    }
+}
+
+/// The base directory a browser data path is relative to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    /// The user's home directory
+    Home,
+    /// `$XDG_CONFIG_HOME`, falling back to `~/.config`
+    Config,
+    /// `$XDG_DATA_HOME`, falling back to `~/.local/share`
+    Data,
+}
+
+impl Anchor {
+    /// Resolve the anchor against the given home directory
+    pub fn resolve(&self, home: &Path) -> PathBuf {
+        match self {
+            Anchor::Home => home.to_path_buf(),
+            Anchor::Config => xdg_dir("XDG_CONFIG_HOME").unwrap_or_else(|| home.join(".config")),
+            Anchor::Data => {
+                xdg_dir("XDG_DATA_HOME").unwrap_or_else(|| home.join(".local").join("share"))
+            }
+        }
+    }
+}
+
+/// Read an XDG base directory variable, ignoring relative values as the spec requires.
+/// They describe the current user, so they are ignored when searching another root.
+fn xdg_dir(var: &str) -> Option<PathBuf> {
+    if HOME_ROOT.get().is_some() {
+        return None;
+    }
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+/// Data directory of each browser on macOS
+const MACOS_DATA_DIRS: &[(Browser, Anchor, &str)] = &[
+    (
+        Browser::Chrome,
+        Anchor::Home,
+        "Library/Application Support/Google/Chrome",
+    ),
+    (
+        Browser::Brave,
+        Anchor::Home,
+        "Library/Application Support/BraveSoftware/Brave-Browser",
+    ),
+    (
+        Browser::BraveBeta,
+        Anchor::Home,
+        "Library/Application Support/BraveSoftware/Brave-Browser-Beta",
+    ),
+    (Browser::Safari, Anchor::Home, "Library/Safari"),
+    (
+        Browser::Firefox,
+        Anchor::Home,
+        "Library/Application Support/Firefox",
+    ),
+    (
+        Browser::Zen,
+        Anchor::Home,
+        "Library/Application Support/zen",
+    ),
+    (
+        Browser::LibreWolf,
+        Anchor::Home,
+        "Library/Application Support/librewolf",
+    ),
+    (
+        Browser::Floorp,
+        Anchor::Home,
+        "Library/Application Support/Floorp",
+    ),
+    (
+        Browser::Waterfox,
+        Anchor::Home,
+        "Library/Application Support/Waterfox",
+    ),
+    (
+        Browser::Mullvad,
+        Anchor::Home,
+        "Library/Application Support/MullvadBrowser-Data/Browser",
+    ),
+    // qutebrowser keeps history and bookmarks apart, see `merge_profiles`
+    (
+        Browser::Qutebrowser,
+        Anchor::Home,
+        "Library/Application Support/qutebrowser",
+    ),
+    (Browser::Qutebrowser, Anchor::Home, ".qutebrowser"),
+    (
+        Browser::Edge,
+        Anchor::Home,
+        "Library/Application Support/Microsoft Edge",
+    ),
+    (
+        Browser::Opera,
+        Anchor::Home,
+        "Library/Application Support/com.operasoftware.Opera",
+    ),
+    (
+        Browser::Vivaldi,
+        Anchor::Home,
+        "Library/Application Support/Vivaldi",
+    ),
+    (
+        Browser::Arc,
+        Anchor::Home,
+        "Library/Application Support/Arc/User Data",
+    ),
+    (
+        Browser::Chromium,
+        Anchor::Home,
+        "Library/Application Support/Chromium",
+    ),
+    // Ungoogled Chromium keeps Chromium's directory, see `get_available_browsers`
+    (
+        Browser::UngoogledChromium,
+        Anchor::Home,
+        "Library/Application Support/Chromium",
+    ),
+    (
+        Browser::Sidekick,
+        Anchor::Home,
+        "Library/Application Support/Sidekick",
+    ),
+    (
+        Browser::ChromeBeta,
+        Anchor::Home,
+        "Library/Application Support/Google/Chrome Beta",
+    ),
+    (
+        Browser::ChromeDev,
+        Anchor::Home,
+        "Library/Application Support/Google/Chrome Dev",
+    ),
+    (
+        Browser::ChromeCanary,
+        Anchor::Home,
+        "Library/Application Support/Google/Chrome Canary",
+    ),
+    (
+        Browser::BraveNightly,
+        Anchor::Home,
+        "Library/Application Support/BraveSoftware/Brave-Browser-Nightly",
+    ),
+    (
+        Browser::EdgeBeta,
+        Anchor::Home,
+        "Library/Application Support/Microsoft Edge Beta",
+    ),
+    (
+        Browser::EdgeDev,
+        Anchor::Home,
+        "Library/Application Support/Microsoft Edge Dev",
+    ),
+    (
+        Browser::EdgeCanary,
+        Anchor::Home,
+        "Library/Application Support/Microsoft Edge Canary",
+    ),
+    (
+        Browser::OperaGx,
+        Anchor::Home,
+        "Library/Application Support/com.operasoftware.OperaGX",
+    ),
+    (
+        Browser::VivaldiSnapshot,
+        Anchor::Home,
+        "Library/Application Support/Vivaldi Snapshot",
+    ),
+    (
+        Browser::Thorium,
+        Anchor::Home,
+        "Library/Application Support/Thorium",
+    ),
+    (
+        Browser::Yandex,
+        Anchor::Home,
+        "Library/Application Support/Yandex/YandexBrowser",
+    ),
+    (
+        Browser::Helium,
+        Anchor::Home,
+        "Library/Application Support/net.imput.helium",
+    ),
+];
+
+/// Data directory of each browser on Linux
+const LINUX_DATA_DIRS: &[(Browser, Anchor, &str)] = &[
+    (Browser::Chrome, Anchor::Config, "google-chrome"),
+    (Browser::ChromeBeta, Anchor::Config, "google-chrome-beta"),
+    (
+        Browser::Brave,
+        Anchor::Config,
+        "BraveSoftware/Brave-Browser",
+    ),
+    (
+        Browser::BraveBeta,
+        Anchor::Config,
+        "BraveSoftware/Brave-Browser-Beta",
+    ),
+    (Browser::Edge, Anchor::Config, "microsoft-edge"),
+    (Browser::Opera, Anchor::Config, "opera"),
+    (Browser::Vivaldi, Anchor::Config, "vivaldi"),
+    (Browser::Chromium, Anchor::Config, "chromium"),
+    (Browser::UngoogledChromium, Anchor::Config, "chromium"),
+    (Browser::Sidekick, Anchor::Config, "sidekick"),
+    (Browser::ChromeDev, Anchor::Config, "google-chrome-unstable"),
+    (
+        Browser::ChromeCanary,
+        Anchor::Config,
+        "google-chrome-canary",
+    ),
+    (
+        Browser::BraveNightly,
+        Anchor::Config,
+        "BraveSoftware/Brave-Browser-Nightly",
+    ),
+    (Browser::EdgeBeta, Anchor::Config, "microsoft-edge-beta"),
+    (Browser::EdgeDev, Anchor::Config, "microsoft-edge-dev"),
+    (Browser::VivaldiSnapshot, Anchor::Config, "vivaldi-snapshot"),
+    (Browser::Thorium, Anchor::Config, "thorium"),
+    (Browser::Yandex, Anchor::Config, "yandex-browser"),
+    (Browser::Helium, Anchor::Config, "net.imput.helium"),
+    (Browser::Firefox, Anchor::Home, ".mozilla/firefox"),
+    (Browser::Zen, Anchor::Home, ".zen"),
+    (Browser::LibreWolf, Anchor::Home, ".librewolf"),
+    (Browser::Floorp, Anchor::Home, ".floorp"),
+    (Browser::Waterfox, Anchor::Home, ".waterfox"),
+    (Browser::Qutebrowser, Anchor::Data, "qutebrowser"),
+    (Browser::Qutebrowser, Anchor::Config, "qutebrowser"),
+    (Browser::Epiphany, Anchor::Data, "epiphany"),
+    (Browser::Falkon, Anchor::Config, "falkon"),
+    (Browser::Mullvad, Anchor::Home, ".mullvad/mullvadbrowser"),
+];
+
+/// Data directory of each sandboxed (Flatpak / Snap) browser on Linux, relative to HOME
+const LINUX_SANDBOXED_DATA_DIRS: &[(Browser, Install, &str)] = &[
+    (
+        Browser::Chrome,
+        Install::Flatpak,
+        ".var/app/com.google.Chrome/config/google-chrome",
+    ),
+    (
+        Browser::Brave,
+        Install::Flatpak,
+        ".var/app/com.brave.Browser/config/BraveSoftware/Brave-Browser",
+    ),
+    (
+        Browser::Edge,
+        Install::Flatpak,
+        ".var/app/com.microsoft.Edge/config/microsoft-edge",
+    ),
+    (
+        Browser::Opera,
+        Install::Flatpak,
+        ".var/app/com.opera.Opera/config/opera",
+    ),
+    (
+        Browser::Vivaldi,
+        Install::Flatpak,
+        ".var/app/com.vivaldi.Vivaldi/config/vivaldi",
+    ),
+    (
+        Browser::Chromium,
+        Install::Flatpak,
+        ".var/app/org.chromium.Chromium/config/chromium",
+    ),
+    (
+        Browser::UngoogledChromium,
+        Install::Flatpak,
+        ".var/app/io.github.ungoogled_software.ungoogled_chromium/config/chromium",
+    ),
+    (
+        Browser::Firefox,
+        Install::Flatpak,
+        ".var/app/org.mozilla.firefox/.mozilla/firefox",
+    ),
+    (
+        Browser::Zen,
+        Install::Flatpak,
+        ".var/app/app.zen_browser.zen/.zen",
+    ),
+    (
+        Browser::LibreWolf,
+        Install::Flatpak,
+        ".var/app/io.gitlab.librewolf-community/.librewolf",
+    ),
+    (
+        Browser::Floorp,
+        Install::Flatpak,
+        ".var/app/one.ablaze.floorp/.floorp",
+    ),
+    (
+        Browser::Waterfox,
+        Install::Flatpak,
+        ".var/app/net.waterfox.waterfox/.waterfox",
+    ),
+    (
+        Browser::Qutebrowser,
+        Install::Flatpak,
+        ".var/app/org.qutebrowser.qutebrowser/data/qutebrowser",
+    ),
+    (
+        Browser::Qutebrowser,
+        Install::Flatpak,
+        ".var/app/org.qutebrowser.qutebrowser/config/qutebrowser",
+    ),
+    (
+        Browser::Epiphany,
+        Install::Flatpak,
+        ".var/app/org.gnome.Epiphany/data/epiphany",
+    ),
+    (
+        Browser::Falkon,
+        Install::Flatpak,
+        ".var/app/org.kde.falkon/config/falkon",
+    ),
+    (
+        Browser::Brave,
+        Install::Snap,
+        "snap/brave/current/.config/BraveSoftware/Brave-Browser",
+    ),
+    (
+        Browser::Chromium,
+        Install::Snap,
+        "snap/chromium/common/chromium",
+    ),
+    (
+        Browser::Firefox,
+        Install::Snap,
+        "snap/firefox/common/.mozilla/firefox",
+    ),
+];
+
+/// Represents the profiles discovered for a browser
 #[derive(Debug)]
 pub struct BrowserPaths {
-    pub history: Option<PathBuf>,
-    pub bookmarks: Option<PathBuf>,
+    pub profiles: Vec<Profile>,
+    /// Whether the browser is running, `None` where this cannot be detected
+    pub running: Option<bool>,
+}
+
+/// A browser data directory probed during discovery
+#[derive(Debug, Clone)]
+pub struct DataDir {
+    pub browser: Browser,
+    pub install: Install,
+    pub path: PathBuf,
+}
+
+/// The home directory to search and the platforms whose layouts apply to it
+pub fn search_home() -> Option<(PathBuf, Vec<Platform>)> {
+    match HOME_ROOT.get() {
+        // A root may have been copied from any platform, so probe them all
+        Some(root) => Some((root.clone(), Platform::ALL.to_vec())),
+        None => Some((home_dir()?, vec![Platform::current()?])),
+    }
+}
+
+/// Every data directory probed for the built-in browsers, enabled or not
+pub fn probed_dirs(home: &Path, platforms: &[Platform]) -> Vec<DataDir> {
+    platforms
+        .iter()
+        .flat_map(Platform::data_dirs)
+        .map(|(browser, install, anchor, data_dir)| DataDir {
+            browser,
+            install,
+            // Paths in the table are relative to their anchor
+            path: anchor.resolve(home).join(data_dir),
+        })
+        .collect()
+}
+
+/// Find every profile of a browser declared in the config file
+pub fn custom_profiles(custom: &config::CustomBrowser, home: &Path) -> Vec<Profile> {
+    let profiles = match &custom.profile_root {
+        Some(root) => discover_profiles(custom.engine, &config::expand_home(root, home)),
+        // An empty path never exists, so missing entries are simply skipped
+        None => vec![Profile::new(
+            "Default",
+            custom
+                .history
+                .as_ref()
+                .map(|path| config::expand_home(path, home))
+                .unwrap_or_default(),
+            custom
+                .bookmarks
+                .as_ref()
+                .map(|path| config::expand_home(path, home))
+                .unwrap_or_default(),
+        )],
+    };
+
+    profiles.into_iter().filter(Profile::has_data).collect()
 }
 
 /// Get all available browsers on the system
 pub fn get_available_browsers() -> HashMap<Browser, BrowserPaths> {
-    let mut browsers = HashMap::new();
-    let home = match home_dir() {
-        Some(path) => path,
-        None => return browsers,
+    let mut browsers: HashMap<Browser, BrowserPaths> = HashMap::new();
+    let Some((home, platforms)) = search_home() else {
+        return browsers;
     };
 
-    // Define paths for each browser
-    let browser_configs = [
-        (
-            Browser::Chrome,
-            "Library/Application Support/Google/Chrome/Default/History",
-            "Library/Application Support/Google/Chrome/Default/Bookmarks",
-        ),
-        (
-            Browser::Brave,
-            "Library/Application Support/BraveSoftware/Brave-Browser/Default/History",
-            "Library/Application Support/BraveSoftware/Brave-Browser/Default/Bookmarks",
-        ),
-        (
-            Browser::BraveBeta,
-            "Library/Application Support/BraveSoftware/Brave-Browser-Beta/Default/History",
-            "Library/Application Support/BraveSoftware/Brave-Browser-Beta/Default/Bookmarks",
-        ),
-        (
-            Browser::Safari,
-            "Library/Safari/History.db",
-            "Library/Safari/Bookmarks.plist",
-        ),
-        (
-            Browser::Firefox,
-            "Library/Application Support/Firefox/Profiles",
-            "Library/Application Support/Firefox/Profiles",
-        ),
-        (
-            Browser::Zen,
-            "Library/Application Support/zen/Profiles",
-            "Library/Application Support/zen/Profiles",
-        ),
-        (
-            Browser::Edge,
-            "Library/Application Support/Microsoft Edge/Default/History",
-            "Library/Application Support/Microsoft Edge/Default/Bookmarks",
-        ),
-        (
-            Browser::Opera,
-            "Library/Application Support/com.operasoftware.Opera/History",
-            "Library/Application Support/com.operasoftware.Opera/Bookmarks",
-        ),
-        (
-            Browser::Vivaldi,
-            "Library/Application Support/Vivaldi/Default/History",
-            "Library/Application Support/Vivaldi/Default/Bookmarks",
-        ),
-        (
-            Browser::Arc,
-            "Library/Application Support/Arc/User Data/Default/History",
-            "Library/Application Support/Arc/User Data/Default/Bookmarks",
-        ),
-        (
-            Browser::Chromium,
-            "Library/Application Support/Chromium/Default/History",
-            "Library/Application Support/Chromium/Default/Bookmarks",
-        ),
-        (
-            Browser::Sidekick,
-            "Library/Application Support/Sidekick/Default/History",
-            "Library/Application Support/Sidekick/Default/Bookmarks",
-        ),
-        (
-            Browser::ChromeBeta,
-            "Library/Application Support/Google/ChromeBeta/Default/History",
-            "Library/Application Support/Google/ChromeBeta/Default/Bookmarks",
-        ),
-    ];
+    let data_dirs = probed_dirs(&home, &platforms);
+    let mut claimed: Vec<&Path> = Vec::new();
+    for DataDir {
+        browser,
+        install,
+        path,
+    } in data_dirs.iter()
+    {
+        // Forks such as Ungoogled Chromium share their upstream's directory
+        // natively, which goes to whichever enabled browser comes first
+        if !browser.is_enabled() || claimed.iter().any(|dir| same_path(dir, path)) {
+            continue;
+        }
+        claimed.push(path);
+        let (browser, install) = (*browser, *install);
 
-    for (browser, history_path, bookmarks_path) in browser_configs {
+        let mut profiles = discover_profiles(browser.engine(), path);
+        for profile in &mut profiles {
+            profile.install = install;
+        }
+
+        profiles.retain(is_profile_selected);
+        mark_locked_profiles(browser.engine(), path, &mut profiles);
+        log::trace!(
+            "Found {} {} profile(s) for {:?} in {:?}",
+            profiles.len(),
+            install.name(),
+            browser,
+            path
+        );
+
+        // Installs of the same browser are merged into one entry
+        let paths = browsers.entry(browser).or_insert_with(|| BrowserPaths {
+            profiles: Vec::new(),
+            running: None,
+        });
+        merge_profiles(&mut paths.profiles, profiles);
+    }
+
+    // Directories browsers were launched with through `--user-data-dir` / `-profile`
+    for (browser, dir) in registry::custom_profile_dirs() {
         if !browser.is_enabled() {
             continue;
         }
 
-        // Join the home directory as paths are relative.
-        // Mutable for Firefox support later.
-        let mut history = home.join(history_path);
-        let mut bookmarks = home.join(bookmarks_path);
-
-        // If it's a variant of firefox.
-        if browser.is_firefox_like() {
-            // Scan each profile directory for places.sqlite
-            if let Ok(entries) = fs::read_dir(&history) {
-                // Get stored profiles
-                for entry in entries.flatten() {
-                    let profile_dir = entry.path();
-                    if profile_dir.is_dir() {
-                        // Scan for a places.sqlite in each
-                        let db = profile_dir.join("places.sqlite");
-                        if db.is_file() {
-                            // history _and_ bookmarks live in the same DB
-                            history.push(db.clone());
-                            bookmarks.push(db);
-                            break; // Only support for one entry for now
-                        }
-                    }
-                }
-            }
+        // A launch may name a profile that discovery already found, such as
+        // `-profile` pointing at the default Firefox profile
+        let mut profiles = launched_profiles(browser.engine(), &dir);
+        profiles.retain(|profile| !is_known_profile(&browsers, profile));
+        profiles.retain(is_profile_selected);
+        mark_locked_profiles(browser.engine(), &dir, &mut profiles);
+        log::trace!(
+            "Found {} launched profile(s) for {:?} in {:?}",
+            profiles.len(),
+            browser,
+            dir
+        );
+
+        let paths = browsers.entry(browser).or_insert_with(|| BrowserPaths {
+            profiles: Vec::new(),
+            running: None,
+        });
+        merge_profiles(&mut paths.profiles, profiles);
+    }
+
+    // Browsers declared in the config file
+    for (index, custom) in config::custom_browsers().iter().enumerate() {
+        let browser = Browser::Custom(index);
+        if !browser.is_enabled() {
+            continue;
         }
 
+        let mut profiles = custom_profiles(custom, &home);
+        profiles.retain(is_profile_selected);
+        if let Some(root) = &custom.profile_root {
+            mark_locked_profiles(
+                custom.engine,
+                &config::expand_home(root, &home),
+                &mut profiles,
+            );
+        }
+        log::trace!("Found {} profile(s) for {:?}", profiles.len(), custom.name);
+
         browsers.insert(
             browser,
             BrowserPaths {
-                history: if history.exists() {
-                    Some(history)
-                } else {
-                    None
-                },
-                bookmarks: if bookmarks.exists() {
-                    Some(bookmarks)
-                } else {
-                    None
-                },
+                profiles,
+                running: None,
             },
         );
     }
 
+    // A live profile lock also means the browser is running
+    for (browser, paths) in &mut browsers {
+        paths.running = running::is_running(browser).map(|running| {
+            running
+                || paths
+                    .profiles
+                    .iter()
+                    .any(|profile| profile.locked == Some(true))
+        });
+    }
+
     browsers
 }
+
+/// Find the profiles of a directory a browser was launched with, named after its path.
+/// Chromium takes a whole user-data directory while Firefox takes a single profile.
+pub fn launched_profiles(engine: Engine, dir: &Path) -> Vec<Profile> {
+    let label = dir.display().to_string();
+    match engine {
+        Engine::Chromium => chromium_profiles(dir)
+            .into_iter()
+            .map(|mut profile| {
+                profile.name = format!("{} ({})", profile.name, label);
+                profile
+            })
+            .collect(),
+        Engine::Gecko => Some(gecko_profile(label, dir))
+            .filter(Profile::has_data)
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns true if a profile reads the same files as one already found
+fn is_known_profile(browsers: &HashMap<Browser, BrowserPaths>, profile: &Profile) -> bool {
+    let same_file = |a: &Option<PathBuf>, b: &Option<PathBuf>| match (a, b) {
+        (Some(a), Some(b)) => same_path(a, b),
+        _ => false,
+    };
+    browsers
+        .values()
+        .flat_map(|paths| &paths.profiles)
+        .any(|known| {
+            same_file(&known.history, &profile.history)
+                || same_file(&known.bookmarks, &profile.bookmarks)
+        })
+}
+
+/// Returns true if both paths name the same file or directory
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Flag the profiles of a data directory that a running browser holds open
+pub fn mark_locked_profiles(engine: Engine, data_dir: &Path, profiles: &mut [Profile]) {
+    for profile in profiles {
+        let profile_dir = profile
+            .history
+            .as_deref()
+            .or(profile.bookmarks.as_deref())
+            .and_then(Path::parent)
+            .unwrap_or(data_dir);
+        profile.locked = running::is_profile_locked(engine, profile.install, data_dir, profile_dir);
+    }
+}
+
+/// Add newly found profiles to a browser's list.
+///
+/// Some browsers spread one profile over several directories (qutebrowser
+/// keeps history under XDG data and bookmarks under XDG config), so a profile
+/// matching an existing one by name and install fills in its missing files.
+fn merge_profiles(existing: &mut Vec<Profile>, found: Vec<Profile>) {
+    for profile in found {
+        match existing
+            .iter_mut()
+            .find(|p| p.name == profile.name && p.install == profile.install)
+        {
+            Some(known) => {
+                known.history = known.history.take().or(profile.history);
+                known.bookmarks = known.bookmarks.take().or(profile.bookmarks);
+                // One live lock holds the whole profile
+                known.locked = match (known.locked, profile.locked) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                };
+            }
+            None => existing.push(profile),
+        }
+    }
+}
+
+/// Find the profiles of a browser data directory according to its engine
+pub fn discover_profiles(engine: Engine, root: &Path) -> Vec<Profile> {
+    match engine {
+        Engine::WebKit => vec![Profile::new(
+            "Default",
+            root.join("History.db"),
+            root.join("Bookmarks.plist"),
+        )],
+        Engine::Gecko => gecko_profiles(root),
+        Engine::Chromium => chromium_profiles(root),
+        Engine::Qutebrowser => vec![qutebrowser_profile(root)],
+        Engine::Epiphany => vec![Profile::new(
+            "Default",
+            root.join("ephy-history.db"),
+            root.join("bookmarks.gvdb"),
+        )],
+        Engine::Falkon => falkon_profiles(root),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Position of every built-in variant in `Browser::BUILT_IN`. The match
+    /// is exhaustive, so a new variant does not build until it is listed.
+    fn built_in_position(browser: Browser) -> Option<usize> {
+        Some(match browser {
+            Browser::Chrome => 0,
+            Browser::ChromeBeta => 1,
+            Browser::Brave => 2,
+            Browser::BraveBeta => 3,
+            Browser::Safari => 4,
+            Browser::Firefox => 5,
+            Browser::Edge => 6,
+            Browser::Zen => 7,
+            Browser::Opera => 8,
+            Browser::Vivaldi => 9,
+            Browser::Arc => 10,
+            Browser::Chromium => 11,
+            Browser::Sidekick => 12,
+            Browser::ChromeDev => 13,
+            Browser::ChromeCanary => 14,
+            Browser::BraveNightly => 15,
+            Browser::EdgeBeta => 16,
+            Browser::EdgeDev => 17,
+            Browser::EdgeCanary => 18,
+            Browser::OperaGx => 19,
+            Browser::VivaldiSnapshot => 20,
+            Browser::Thorium => 21,
+            Browser::UngoogledChromium => 22,
+            Browser::Yandex => 23,
+            Browser::Helium => 24,
+            Browser::LibreWolf => 25,
+            Browser::Floorp => 26,
+            Browser::Waterfox => 27,
+            Browser::Mullvad => 28,
+            Browser::Qutebrowser => 29,
+            Browser::Epiphany => 30,
+            Browser::Falkon => 31,
+            Browser::Custom(_) => return None,
+        })
+    }
+
+    #[test]
+    fn built_in_lists_every_browser_once() {
+        for (index, browser) in Browser::BUILT_IN.into_iter().enumerate() {
+            assert_eq!(built_in_position(browser), Some(index), "{:?}", browser);
+        }
+    }
+
+    #[test]
+    fn env_vars_find_their_browser() {
+        for browser in Browser::BUILT_IN {
+            assert_eq!(Browser::from_env_var(browser.env_var()), Some(browser));
+        }
+    }
+
+    #[test]
+    fn macos_chrome_channels_live_next_to_chrome() {
+        let dir = |browser: Browser| {
+            MACOS_DATA_DIRS
+                .iter()
+                .find(|(b, _, _)| *b == browser)
+                .map(|(_, anchor, path)| (*anchor, *path))
+        };
+        for (browser, path) in [
+            (Browser::ChromeBeta, "Google/Chrome Beta"),
+            (Browser::ChromeDev, "Google/Chrome Dev"),
+            (Browser::ChromeCanary, "Google/Chrome Canary"),
+        ] {
+            assert_eq!(
+                dir(browser),
+                Some((
+                    Anchor::Home,
+                    format!("Library/Application Support/{}", path).as_str()
+                )),
+                "{:?}",
+                browser
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Loads user-defined browsers from a JSON config file.
+//!
+//! Defines:
+//! - `CustomBrowser` describing a browser by name, engine and paths
+//! - `custom_browsers` / `custom_browser` giving access to the entries,
+//!   read once from `$browsers_config` or the default config location
+//! - `expand_home` to resolve `~/` in configured paths, rebasing absolute
+//!   ones onto the root set through `--root`.
+//!
+//! Example `browsers.json`:
+//!
+//! ```json
+//! {
+//!   "browsers": [
+//!     { "name": "Corp Chrome", "engine": "chromium", "profile_root": "~/.config/corp-chrome" },
+//!     { "name": "Nightly", "engine": "gecko", "history": "~/nightly/places.sqlite" }
+//!   ]
+//! }
+//! ```
+
+use crate::browser::{self, Engine};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+/// A browser declared in the config file
+#[derive(Debug, Deserialize)]
+pub struct CustomBrowser {
+    /// Display name, also used in logs
+    pub name: String,
+    /// Engine family, deciding which readers are used
+    pub engine: Engine,
+    /// Explicit history file, used when no `profile_root` is given
+    #[serde(default)]
+    pub history: Option<PathBuf>,
+    /// Explicit bookmarks file, used when no `profile_root` is given
+    #[serde(default)]
+    pub bookmarks: Option<PathBuf>,
+    /// Data directory to discover profiles in, like the built-in browsers
+    #[serde(default)]
+    pub profile_root: Option<PathBuf>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// Top-level layout of the config file
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    #[serde(default)]
+    browsers: Vec<CustomBrowser>,
+}
+
+/// Get the path of the config file, overridable through `$browsers_config`
+fn get_config_file() -> Option<PathBuf> {
+    match std::env::var_os("browsers_config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(
+            dirs::config_dir()?
+                .join("browserSearch")
+                .join("browsers.json"),
+        ),
+    }
+}
+
+/// Read the config file, treating a missing or invalid file as empty
+fn load_config() -> Config {
+    let Some(path) = get_config_file() else {
+        return Config::default();
+    };
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => return Config::default(),
+    };
+
+    match serde_json::from_str(&contents) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Error parsing browser config {:?}: {}", path, e);
+            Config::default()
+        }
+    }
+}
+
+/// Every browser declared in the config file
+pub fn custom_browsers() -> &'static [CustomBrowser] {
+    static BROWSERS: OnceLock<Vec<CustomBrowser>> = OnceLock::new();
+    BROWSERS.get_or_init(|| load_config().browsers)
+}
+
+/// Get a declared browser by its index in the config file
+pub fn custom_browser(index: usize) -> &'static CustomBrowser {
+    &custom_browsers()[index]
+}
+
+/// Resolve a leading `~` against the home directory. While another root is
+/// searched, absolute paths are rebased onto it too, so no entry reads the
+/// live system.
+pub fn expand_home(path: &Path, home: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix("~") {
+        return home.join(rest);
+    }
+    if browser::has_home_root() && path.has_root() {
+        return rebase(path, home, dirs::home_dir().as_deref());
+    }
+    path.to_path_buf()
+}
+
+/// Move an absolute path onto `root`. Paths in the live home keep only their
+/// part below it, as the root stands in for that home.
+fn rebase(path: &Path, root: &Path, live_home: Option<&Path>) -> PathBuf {
+    let relative = match live_home.map(|home| path.strip_prefix(home)) {
+        Some(Ok(rest)) => rest.to_path_buf(),
+        _ => path
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect(),
+    };
+    root.join(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn absolute_paths_are_rebased_onto_the_root() {
+        let root = Path::new("/mnt/backup");
+        let home = Some(Path::new("/home/me"));
+        assert_eq!(
+            rebase(Path::new("/home/me/.config/corp/History"), root, home),
+            root.join(".config/corp/History")
+        );
+        assert_eq!(
+            rebase(Path::new("/opt/corp/places.sqlite"), root, home),
+            root.join("opt/corp/places.sqlite")
+        );
+        assert_eq!(
+            rebase(Path::new("/opt/corp/places.sqlite"), root, None),
+            root.join("opt/corp/places.sqlite")
+        );
+    }
+}
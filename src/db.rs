@@ -1,156 +1,578 @@
 //! Helpers for safely querying SQLite browser databases.
 //!
-//! - `create_temp_db_copy` to make a read-only tempfile copy,
-//!   verifying integrity before use.
-//! - `query_chrome_history` and `query_safari_history` wrappers to
-//!   prepare, execute, and map query results.
+//! - `open_browser_db` to open a browser database read-only in place,
+//!   falling back to a snapshot when the browser holds it.
+//! - `Pushdown` / `query_pushed_down` running a history query with the
+//!   `WHERE` clauses and `LIMIT` of a search, and mapping its rows.
+//! - `MATCH_FOLD` the SQL function those clauses use to fold titles the
+//!   way the fuzzy matcher sees them.
+//! - `QuerySet` / `schema_version` / `query_with_schema` to pick the query
+//!   variant matching a database's schema version, degrading to a minimal
+//!   query for versions not known yet.
+//! - `query_or_salvage` falling back to reading a damaged table row by row,
+//!   keeping whatever rows are still readable.
 
-use rusqlite::{Connection, Result as SqliteResult, Row};
+use crate::snapshot::{has_pending_wal, open_snapshot};
+use nucleo::chars;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use rusqlite::{
+    params_from_iter, Connection, ErrorCode, OpenFlags, Result as SqliteResult, Row, Statement,
+};
 use std::error::Error;
 use std::fs;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::time::Duration;
 use tempfile::NamedTempFile;
+use url::Url;
 
-/// Create a temporary copy of an SQLite database for safe reading
-pub fn create_temp_db_copy(
+/// Open a browser database for reading, without copying it when possible.
+///
+/// The original is opened in place with `mode=ro`, which honours the locks
+/// of a running browser and reads what it has not yet checkpointed from the
+/// `-wal`. Only when the browser has the database locked is a snapshot,
+/// including the `-wal`, made. When opening is refused for another reason
+/// (e.g. a WAL database in a read-only directory) it is opened
+/// `immutable=1`, but only once `locked` (see `running::is_profile_locked`)
+/// tells no browser holds the profile and the `-wal` holds no pending
+/// frames, as an immutable reader sees neither. Otherwise it is snapshotted.
+/// Snapshots are kept in the store of the `snapshot` module, but one that
+/// could not be stored is a temp file that must be kept alive as long as
+/// the connection.
+pub fn open_browser_db(
     db_path: &Path,
-    temp_dir: Option<&Path>,
-    temp_filename_prefix: Option<&str>,
-) -> Result<(NamedTempFile, Connection), Box<dyn Error>> {
-    log::trace!("Begining the creation of the temporary database");
-    // Define temp file path components
-
-    let prefix = temp_filename_prefix.unwrap_or("db_temp");
-
-    // Try to find an existing temp file with the prefix in the provided temp directory
-    let temp_file = if let Some(dir) = temp_dir {
-        if !dir.exists() {
-            fs::create_dir_all(dir)?;
+    locked: Option<bool>,
+) -> Result<(Option<NamedTempFile>, Connection), Box<dyn Error>> {
+    let e = match open_in_place(db_path, "mode=ro") {
+        Ok(conn) => {
+            log::debug!("Reading {:?} in place (mode=ro)", db_path);
+            return Ok((None, conn));
         }
+        Err(e) if is_locked(e.as_ref()) => {
+            log::debug!("Snapshotting {:?}: it is locked ({})", db_path, e);
+            return open_snapshot(db_path);
+        }
+        Err(e) => e,
+    };
 
-        // Check for existing temp files with our prefix
-        let entries = fs::read_dir(dir)?;
-        let mut existing_temp_path = None;
-
-        // Iterate through canidates
-        for entry in entries.flatten() {
-            let file_name = entry.file_name();
-            let file_name_str = file_name.to_string_lossy();
+    if locked != Some(false) {
+        log::debug!(
+            "Snapshotting {:?}: it cannot be opened in place ({}) while it may be in use",
+            db_path,
+            e
+        );
+        return open_snapshot(db_path);
+    }
+    if has_pending_wal(db_path) {
+        log::debug!(
+            "Snapshotting {:?}: it cannot be opened in place ({}) and its WAL has pending frames",
+            db_path,
+            e
+        );
+        return open_snapshot(db_path);
+    }
 
-            if file_name_str.starts_with(prefix) && entry.path().is_file() {
-                // Found a match!
-                existing_temp_path = Some(entry.path());
-                break;
-            }
+    match open_in_place(db_path, "immutable=1") {
+        Ok(conn) => {
+            log::debug!(
+                "Reading {:?} in place (immutable, mode=ro failed: {})",
+                db_path,
+                e
+            );
+            Ok((None, conn))
         }
+        Err(e) => {
+            log::debug!(
+                "Snapshotting {:?}: it cannot be opened in place ({})",
+                db_path,
+                e
+            );
+            open_snapshot(db_path)
+        }
+    }
+}
 
-        if let Some(path) = existing_temp_path {
-            // Check if we can read the existing temp file
-            match fs::metadata(&path) {
-                Ok(_) => {
-                    let temp_file = NamedTempFile::new_in(dir)?;
-                    let temp_path = temp_file.path().to_path_buf();
-                    fs::copy(&path, &temp_path)?;
-                    fs::remove_file(path)?; // Remove the old file we found
-                    temp_file
-                }
-                Err(_) => {
-                    // Couldn't access existing file, create a new one
-                    NamedTempFile::with_prefix_in(prefix, dir)?
-                }
-            }
-        } else {
-            // No existing file found, create a new one
-            NamedTempFile::with_prefix_in(prefix, dir)?
+/// Open the original file read-only through an SQLite URI, checking it can be read
+fn open_in_place(db_path: &Path, params: &str) -> Result<Connection, Box<dyn Error>> {
+    let path = fs::canonicalize(db_path)?;
+    let url = Url::from_file_path(&path).map_err(|_| format!("Invalid path {:?}", path))?;
+    let conn = Connection::open_with_flags(
+        format!("{}?{}", url, params),
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+
+    // Opening is lazy, so touch the schema to hit any lock or format error
+    // now, without waiting for the browser to release its lock
+    conn.busy_timeout(Duration::ZERO)?;
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    Ok(conn)
+}
+
+/// Returns true if the error comes from another connection holding a lock
+fn is_locked(error: &(dyn Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(e, _))
+            if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Returns true if the error means the database file is damaged
+pub fn is_corrupt(error: &(dyn Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(e, _))
+            if matches!(e.code, ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)
+    )
+}
+
+/// Predicates and a limit wrapped around a history query.
+///
+/// The query becomes a subquery, so the clauses name its result columns
+/// and SQLite pushes them down into its scans where it can.
+#[derive(Debug, Default)]
+pub struct Pushdown {
+    pub clauses: Vec<String>,
+    pub params: Vec<Value>,
+    pub limit: Option<usize>,
+}
+
+impl Pushdown {
+    /// Add a clause with one `?` parameter
+    pub fn push(&mut self, clause: &str, param: Value) {
+        self.push_all(clause, vec![param]);
+    }
+
+    /// Add a clause with several `?` parameters
+    pub fn push_all(&mut self, clause: &str, params: Vec<Value>) {
+        self.clauses.push(clause.to_string());
+        self.params.extend(params);
+    }
+
+    /// Wrap a query returning `last_visit_time` in the predicates and limit
+    ///
+    /// Without predicates or limit the query runs as it is, which also suits
+    /// queries without that column, such as bookmarks.
+    pub fn wrap(&self, sql: &str) -> String {
+        if self.clauses.is_empty() && self.limit.is_none() {
+            return sql.to_string();
         }
-    } else {
-        // No specific directory specified, create a new temp file in the default location
-        NamedTempFile::with_prefix(prefix)?
-    };
 
-    let temp_path = temp_file.path().to_path_buf();
-
-    // Copy the database to the temporary file if needed
-    // Compare file sizes to determine if copy is needed
-    let db_metadata = fs::metadata(db_path)?;
-    let temp_metadata = fs::metadata(&temp_path)?;
-
-    if temp_metadata.len() != db_metadata.len() {
-        // Different sizes, we need to copy
-        fs::copy(db_path, &temp_path)?;
-    } else {
-        // Same size, but we should verify it's actually a valid SQLite database
-        match Connection::open(&temp_path) {
-            Ok(conn) => {
-                // Try a simple query to verify integrity
-                if conn
-                    .query_row("PRAGMA integrity_check", [], |row| {
-                        let result: String = row.get(0)?;
-                        Ok(result)
-                    })
-                    .is_err()
-                {
-                    // Database seems corrupted, copy again
-                    fs::copy(db_path, &temp_path)?;
-                }
-            }
-            Err(_) => {
-                // Couldn't open the database, copy again
-                fs::copy(db_path, &temp_path)?;
-            }
+        let mut wrapped = format!("SELECT * FROM ({})", sql);
+        if !self.clauses.is_empty() {
+            wrapped.push_str(" WHERE ");
+            wrapped.push_str(&self.clauses.join(" AND "));
         }
+        wrapped.push_str(" ORDER BY last_visit_time DESC");
+        if let Some(limit) = self.limit {
+            wrapped.push_str(&format!(" LIMIT {}", limit));
+        }
+        wrapped
     }
 
-    // Connect to the temporary database
-    let conn = Connection::open(&temp_path)?;
-    Ok((temp_file, conn))
+    /// Prepare a wrapped query, registering the functions the predicates call
+    pub fn prepare<'conn>(
+        &self,
+        conn: &'conn Connection,
+        sql: &str,
+    ) -> SqliteResult<Statement<'conn>> {
+        register_match_fold(conn)?;
+        conn.prepare(&self.wrap(sql))
+    }
+}
+
+/// SQL function folding text as the fuzzy matcher folds a title: the first
+/// character of each grapheme, with latin accents stripped and its case
+/// folded. Anything but text becomes `NULL`.
+pub const MATCH_FOLD: &str = "match_fold";
+
+/// Fold text as `nucleo` does with `Config::DEFAULT` before matching
+pub fn match_fold(text: &str) -> String {
+    chars::graphemes(text)
+        .map(|c| chars::to_lower_case(chars::normalize(c)))
+        .collect()
 }
 
-/// Execute a query on a Chrome-based history database
-pub fn query_chrome_history<F, T>(
+/// Register `MATCH_FOLD` on a connection
+fn register_match_fold(conn: &Connection) -> SqliteResult<()> {
+    conn.create_scalar_function(
+        MATCH_FOLD,
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get_raw(0).as_str().ok().map(match_fold)),
+    )
+}
+
+/// Run a history query narrowed down by the predicates of a search
+pub fn query_pushed_down<F, T>(
     conn: &Connection,
-    query: &str,
+    sql: &str,
+    pushdown: &Pushdown,
     row_mapper: F,
 ) -> SqliteResult<Vec<T>>
 where
     F: FnMut(&Row<'_>) -> SqliteResult<T>,
 {
-    let mut stmt = conn.prepare(query)?;
+    let mut stmt = pushdown.prepare(conn, sql)?;
     let results = stmt
-        .query_map([], row_mapper)?
+        .query_map(params_from_iter(&pushdown.params), row_mapper)?
         .collect::<Result<Vec<_>, _>>()?;
 
     Ok(results)
 }
 
-/// Execute a query on a Safari history database
-pub fn query_safari_history<F, T>(
+/// One SQL statement for a range of schema versions
+#[derive(Debug)]
+pub struct QueryVariant {
+    /// Short name reported by diagnostics
+    pub name: &'static str,
+    pub versions: RangeInclusive<i64>,
+    pub sql: &'static str,
+}
+
+/// The known variants of a query, and the minimal one to degrade to.
+///
+/// Every variant must return the same columns, in the same order.
+#[derive(Debug)]
+pub struct QuerySet {
+    pub variants: &'static [QueryVariant],
+    /// Relies on as few columns as possible, for versions we do not know
+    pub minimal: &'static str,
+    /// Where to scrape rows from when the database is damaged
+    pub salvage: Option<Salvage>,
+}
+
+/// The table and columns to read row by row from a damaged database.
+///
+/// The columns are those the query variants return.
+#[derive(Debug)]
+pub struct Salvage {
+    pub table: &'static str,
+    pub columns: &'static str,
+}
+
+impl QuerySet {
+    /// Statements to try for a schema version, best first.
+    ///
+    /// A known version gets its variant, an unknown one the newest variant.
+    /// Older variants follow, newest first, then the minimal query, in case
+    /// a database lacks a column its version should have.
+    pub fn candidates(&self, version: Option<i64>) -> Vec<(&'static str, &'static str)> {
+        let mut variants: Vec<&QueryVariant> = self.variants.iter().collect();
+        variants.sort_by_key(|variant| std::cmp::Reverse(*variant.versions.end()));
+
+        let start = version
+            .and_then(|version| {
+                variants
+                    .iter()
+                    .position(|variant| variant.versions.contains(&version))
+            })
+            .unwrap_or_default();
+
+        let mut candidates: Vec<_> = variants[start..]
+            .iter()
+            .map(|variant| (variant.name, variant.sql))
+            .collect();
+        candidates.push(("minimal", self.minimal));
+        candidates
+    }
+
+    /// Name of the statement that runs against this database
+    pub fn pick(&self, conn: &Connection) -> Option<&'static str> {
+        self.candidates(schema_version(conn))
+            .into_iter()
+            .find(|(_, sql)| conn.prepare(sql).is_ok())
+            .map(|(name, _)| name)
+    }
+}
+
+/// Read the schema version of a browser database.
+///
+/// Chromium keeps it in the `meta` table and Safari in `metadata`, both
+/// under the `version` key, while Firefox uses `PRAGMA user_version`.
+pub fn schema_version(conn: &Connection) -> Option<i64> {
+    for table in ["meta", "metadata"] {
+        let version = conn
+            .query_row(
+                &format!("SELECT value FROM {} WHERE key = 'version'", table),
+                [],
+                |row| row.get::<_, rusqlite::types::Value>(0),
+            )
+            .ok();
+        match version {
+            Some(rusqlite::types::Value::Integer(version)) => return Some(version),
+            Some(rusqlite::types::Value::Text(version)) => {
+                if let Ok(version) = version.parse() {
+                    return Some(version);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .ok()
+        .filter(|&version: &i64| version != 0)
+}
+
+/// Run the variant of a query matching the database's schema version,
+/// narrowed down by the predicates of a search.
+///
+/// A variant that does not prepare, such as one naming a column the
+/// schema lacks, gives way to the next candidate.
+pub fn query_with_schema<F, T>(
     conn: &Connection,
-    query: &str,
-    row_mapper: F,
-) -> SqliteResult<Vec<T>>
+    queries: &QuerySet,
+    pushdown: &Pushdown,
+    mut row_mapper: F,
+) -> Result<Vec<T>, Box<dyn Error>>
 where
     F: FnMut(&Row<'_>) -> SqliteResult<T>,
 {
-    let mut stmt = conn.prepare(query)?;
-    let results = stmt
-        .query_map([], row_mapper)?
-        .collect::<Result<Vec<_>, _>>()?;
+    let version = schema_version(conn);
+    let mut last_error = None;
 
-    Ok(results)
+    for (name, sql) in queries.candidates(version) {
+        let mut stmt = match pushdown.prepare(conn, sql) {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                log::warn!("Query {} does not fit schema {:?}: {}", name, version, e);
+                last_error = Some(e);
+                continue;
+            }
+        };
+
+        log::debug!("Using query {} for schema {:?}", name, version);
+        let rows = stmt
+            .query_map(params_from_iter(&pushdown.params), &mut row_mapper)?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        return Ok(rows);
+    }
+
+    Err(last_error.map_or_else(|| "No query to run".into(), Into::into))
 }
 
-pub fn query_firefox_bookmarks<F, T>(
+/// Run a query like `query_with_schema`, salvaging what rows it can when
+/// the database turns out to be damaged.
+///
+/// Returns the rows and whether they were salvaged. Salvaged rows are
+/// narrowed down by the search's predicates, but not cut to its limit.
+pub fn query_or_salvage<F, T>(
     conn: &Connection,
-    sql: &str,
+    queries: &QuerySet,
+    pushdown: &Pushdown,
     mut row_mapper: F,
-) -> SqliteResult<Vec<T>>
+) -> Result<(Vec<T>, bool), Box<dyn Error>>
+where
+    F: FnMut(&Row<'_>) -> SqliteResult<T>,
+{
+    match query_with_schema(conn, queries, pushdown, &mut row_mapper) {
+        Ok(rows) => Ok((rows, false)),
+        Err(e) if is_corrupt(e.as_ref()) => {
+            let Some(salvage) = &queries.salvage else {
+                return Err(e);
+            };
+            log::warn!("Database is damaged ({}), salvaging {}", e, salvage.table);
+            Ok((salvage_rows(conn, salvage, pushdown, row_mapper)?, true))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Rows read per batch while salvaging
+const SALVAGE_BATCH: i64 = 500;
+
+/// Read the rows of a damaged table that can still be read, in the spirit
+/// of SQLite's `.recover`.
+///
+/// The table is walked in rowid order, one batch at a time. When a read
+/// fails the walk jumps ahead, by a step that doubles while reads keep
+/// failing, until it lands beyond the damaged pages or past the last rowid.
+/// Rows whose values cannot be mapped are left out, as are those the
+/// pushdown's predicates reject; its limit is not applied, as the rows come
+/// in rowid order rather than by last visit.
+fn salvage_rows<F, T>(
+    conn: &Connection,
+    salvage: &Salvage,
+    pushdown: &Pushdown,
+    mut row_mapper: F,
+) -> Result<Vec<T>, Box<dyn Error>>
 where
     F: FnMut(&Row<'_>) -> SqliteResult<T>,
 {
-    let mut stmt = conn.prepare(sql)?;
-    let rows = stmt
-        .query_map([], |row| row_mapper(row))?
-        .collect::<SqliteResult<Vec<_>>>()?;
-    Ok(rows)
+    // The rowid and whether the row passes the predicates come after the
+    // columns, so the mapper sees them as usual. Every row of the batch is
+    // returned, so the walk still advances past rows left out.
+    let keep = match pushdown.clauses.is_empty() {
+        true => "1".to_string(),
+        false => pushdown.clauses.join(" AND "),
+    };
+    let sql = format!(
+        "SELECT *, ({}) IS 1 FROM
+         (SELECT {}, rowid FROM {} WHERE rowid > ? ORDER BY rowid LIMIT {})",
+        keep, salvage.columns, salvage.table, SALVAGE_BATCH
+    );
+    register_match_fold(conn)?;
+    let mut stmt = conn.prepare(&sql)?;
+    let rowid_column = stmt.column_count() - 2;
+    let keep_column = stmt.column_count() - 1;
+    // The predicates' parameters come before the cursor in the statement
+    let params = |cursor: i64| {
+        pushdown
+            .params
+            .iter()
+            .cloned()
+            .chain(std::iter::once(Value::Integer(cursor)))
+            .collect::<Vec<_>>()
+    };
+
+    let last_rowid: Option<i64> = conn
+        .query_row(
+            &format!("SELECT MAX(rowid) FROM {}", salvage.table),
+            [],
+            |row| row.get(0),
+        )
+        .unwrap_or(None);
+
+    let mut results = Vec::new();
+    // Browsers never assign rowids below 1
+    let (mut cursor, mut step, mut skipped, mut unreadable) = (0i64, 1i64, 0, 0);
+    loop {
+        let mut read = 0;
+        let mut failed = false;
+        match stmt.query(params_from_iter(params(cursor))) {
+            Ok(mut rows) => loop {
+                match rows.next() {
+                    Ok(Some(row)) => {
+                        read += 1;
+                        cursor = row.get(rowid_column)?;
+                        if !row.get::<_, bool>(keep_column)? {
+                            continue;
+                        }
+                        match row_mapper(row) {
+                            Ok(result) => results.push(result),
+                            Err(_) => unreadable += 1,
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(_) => {
+                        failed = true;
+                        break;
+                    }
+                }
+            },
+            Err(_) => failed = true,
+        }
+
+        if !failed {
+            if read < SALVAGE_BATCH {
+                break;
+            }
+            continue;
+        }
+
+        // Skip past the damage, further each time it is still in the way
+        if read > 0 {
+            step = 1;
+        }
+        skipped += 1;
+        cursor = match cursor.checked_add(step) {
+            Some(cursor) if last_rowid.is_none_or(|last| cursor < last) => cursor,
+            _ => break,
+        };
+        step = step.saturating_mul(2);
+        if last_rowid.is_none() && step > 1 << 32 {
+            break;
+        }
+    }
+
+    log::warn!(
+        "Salvaged {} rows of {}, skipping {} damaged spots and {} unreadable rows",
+        results.len(),
+        salvage.table,
+        skipped,
+        unreadable
+    );
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERIES: QuerySet = QuerySet {
+        variants: &[
+            QueryVariant {
+                name: "old",
+                versions: 1..=9,
+                sql: "SELECT url, title FROM urls",
+            },
+            QueryVariant {
+                name: "new",
+                versions: 10..=20,
+                sql: "SELECT url, title FROM urls WHERE hidden = 0",
+            },
+        ],
+        minimal: "SELECT url, url AS title FROM urls",
+        salvage: None,
+    };
+
+    fn names(version: Option<i64>) -> Vec<&'static str> {
+        QUERIES
+            .candidates(version)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn candidates_fall_back_to_older_variants() {
+        assert_eq!(names(Some(15)), ["new", "old", "minimal"]);
+        assert_eq!(names(Some(5)), ["old", "minimal"]);
+        assert_eq!(names(Some(99)), ["new", "old", "minimal"]);
+        assert_eq!(names(None), ["new", "old", "minimal"]);
+    }
+
+    #[test]
+    fn pick_skips_variants_the_schema_lacks() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE meta (key TEXT, value TEXT);
+             INSERT INTO meta VALUES ('version', '15');
+             CREATE TABLE urls (url TEXT, title TEXT);",
+        )
+        .unwrap();
+        assert_eq!(schema_version(&conn), Some(15));
+        assert_eq!(QUERIES.pick(&conn), Some("old"));
+
+        conn.execute_batch("ALTER TABLE urls ADD COLUMN hidden INTEGER")
+            .unwrap();
+        assert_eq!(QUERIES.pick(&conn), Some("new"));
+    }
+
+    #[test]
+    fn salvage_applies_the_pushdown_across_batches() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE urls (url TEXT, last_visit_time INTEGER);
+             WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1200)
+             INSERT INTO urls SELECT 'https://example.com/' || i, i FROM n;",
+        )
+        .unwrap();
+
+        let salvage = Salvage {
+            table: "urls",
+            columns: "url, last_visit_time",
+        };
+        let mut pushdown = Pushdown::default();
+        pushdown.push("last_visit_time % ? = 0", Value::Integer(400));
+        let times: Vec<i64> = salvage_rows(&conn, &salvage, &pushdown, |row| row.get(1)).unwrap();
+        assert_eq!(times, [400, 800, 1200]);
+    }
 }
@@ -0,0 +1,408 @@
+//! Diagnostics for the `doctor` command.
+//!
+//! Defines:
+//! - `BrowserReport` / `LocationReport` / `ProfileReport` / `FileReport`
+//!   describing what discovery probed and found for every browser
+//! - `diagnose` that probes every known browser, enabled or not, opening
+//!   each SQLite database to read its schema version, the query variant
+//!   picked for it, row counts and any damage, and noting running browsers
+//!   and locked profiles. Databases are read directly, never through the
+//!   history store, so probing disabled browsers leaves nothing behind.
+//! - `run` printing the report as a human table or JSON.
+
+use crate::browser::{
+    custom_profiles, discover_profiles, launched_profiles, mark_locked_profiles, probed_dirs,
+    search_home, Browser, Engine,
+};
+use crate::config;
+use crate::db::{open_browser_db, schema_version, QuerySet};
+use crate::profiles::{is_profile_selected, Profile};
+use crate::query::Query;
+use crate::registry::remembered_profile_dirs;
+use crate::running::is_running;
+use crate::search::SearchResult;
+use crate::source::{source_for, Installed};
+use crate::{bookmarks, history};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// What was found for one browser
+#[derive(Debug, Serialize)]
+pub struct BrowserReport {
+    pub name: String,
+    /// Env var enabling the browser, `None` for browsers from the config file
+    pub env_var: Option<String>,
+    pub enabled: bool,
+    /// Whether a process of the browser is running, `None` where this cannot be detected
+    pub running: Option<bool>,
+    pub locations: Vec<LocationReport>,
+}
+
+/// One data directory probed for a browser
+#[derive(Debug, Serialize)]
+pub struct LocationReport {
+    pub install: String,
+    pub path: PathBuf,
+    pub exists: bool,
+    pub profiles: Vec<ProfileReport>,
+}
+
+/// One profile found in a data directory
+#[derive(Debug, Serialize)]
+pub struct ProfileReport {
+    pub name: String,
+    pub is_default: bool,
+    /// Whether `included_profiles` / `excluded_profiles` let it through
+    pub selected: bool,
+    /// Whether a running browser holds the profile open, `None` where unknown
+    pub locked: Option<bool>,
+    pub account: Option<String>,
+    pub avatar: Option<u32>,
+    pub history: Option<FileReport>,
+    pub bookmarks: Option<FileReport>,
+}
+
+/// One history or bookmarks file of a profile
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// Chromium's `meta` or Safari's `metadata` version, else `PRAGMA user_version`
+    pub schema_version: Option<i64>,
+    /// Query variant the reader picks for that version, `minimal` when unknown
+    pub query: Option<String>,
+    /// Row counts of the tables the readers query
+    pub row_counts: BTreeMap<String, i64>,
+    /// Number of entries the reader returned
+    pub entries: Option<usize>,
+    /// What `PRAGMA quick_check` found wrong with the database
+    pub damage: Vec<String>,
+    /// Whether the reader had to salvage the entries from a damaged database
+    pub recovered: bool,
+    pub error: Option<String>,
+}
+
+/// Tables worth counting in the databases of each engine
+fn counted_tables(engine: Engine) -> &'static [&'static str] {
+    match engine {
+        Engine::Chromium => &["urls", "visits"],
+        Engine::Gecko => &["moz_places", "moz_historyvisits", "moz_bookmarks"],
+        Engine::WebKit => &["history_items", "history_visits"],
+        Engine::Qutebrowser => &["History"],
+        Engine::Epiphany => &["urls", "visits"],
+        Engine::Falkon => &["history"],
+    }
+}
+
+/// Probe every known browser and describe what was found
+pub fn diagnose() -> Vec<BrowserReport> {
+    let Some((home, platforms)) = search_home() else {
+        return Vec::new();
+    };
+
+    let mut reports: Vec<BrowserReport> = Vec::new();
+    for dir in probed_dirs(&home, &platforms) {
+        let mut profiles = discover_profiles(dir.browser.engine(), &dir.path);
+        for profile in &mut profiles {
+            profile.install = dir.install;
+        }
+        mark_locked_profiles(dir.browser.engine(), &dir.path, &mut profiles);
+
+        let location = LocationReport {
+            install: dir.install.name().to_string(),
+            exists: dir.path.exists(),
+            profiles: report_profiles(dir.browser, profiles),
+            path: dir.path,
+        };
+
+        // Directories of the same browser are grouped together
+        match reports.iter_mut().find(|r| r.name == dir.browser.name()) {
+            Some(report) => report.locations.push(location),
+            None => reports.push(BrowserReport {
+                name: dir.browser.name().to_string(),
+                env_var: Some(dir.browser.env_var().to_string()),
+                enabled: dir.browser.is_enabled(),
+                running: is_running(&dir.browser),
+                locations: vec![location],
+            }),
+        }
+    }
+
+    // Directories browsers were launched with
+    for (browser, dir) in remembered_profile_dirs() {
+        let mut profiles = launched_profiles(browser.engine(), &dir);
+        mark_locked_profiles(browser.engine(), &dir, &mut profiles);
+
+        let location = LocationReport {
+            install: "Launched".to_string(),
+            exists: dir.exists(),
+            profiles: report_profiles(browser, profiles),
+            path: dir,
+        };
+        if let Some(report) = reports.iter_mut().find(|r| r.name == browser.name()) {
+            report.locations.push(location);
+        }
+    }
+
+    // Browsers declared in the config file
+    for (index, custom) in config::custom_browsers().iter().enumerate() {
+        let browser = Browser::Custom(index);
+        // The profile root, or else the first file configured explicitly
+        let path = [&custom.profile_root, &custom.history, &custom.bookmarks]
+            .into_iter()
+            .flatten()
+            .next()
+            .map(|path| config::expand_home(path, &home))
+            .unwrap_or_default();
+
+        reports.push(BrowserReport {
+            name: custom.name.clone(),
+            env_var: None,
+            enabled: browser.is_enabled(),
+            running: None,
+            locations: vec![LocationReport {
+                install: "Config".to_string(),
+                exists: path.exists(),
+                path,
+                profiles: report_profiles(browser, custom_profiles(custom, &home)),
+            }],
+        });
+    }
+
+    reports
+}
+
+/// Describe the profiles of a browser, reading each of their files
+fn report_profiles(browser: Browser, profiles: Vec<Profile>) -> Vec<ProfileReport> {
+    let source = source_for(Installed {
+        browser,
+        profiles: Vec::new(),
+        running: None,
+    });
+    let engine = browser.engine();
+    let tables = counted_tables(engine);
+
+    profiles
+        .iter()
+        .map(|profile| ProfileReport {
+            name: profile.name.clone(),
+            is_default: profile.is_default,
+            selected: is_profile_selected(profile),
+            locked: profile.locked,
+            account: profile.account.clone(),
+            avatar: profile.avatar,
+            history: profile.history.as_ref().map(|path| {
+                report_file(
+                    path,
+                    profile.locked,
+                    tables,
+                    history::queries_for(engine),
+                    // Engines synced into the store are read directly, so
+                    // probing leaves no copy of their history behind
+                    || match history::queries_for(engine) {
+                        Some(queries) => {
+                            history::read_history_in_place(path, queries, profile.locked)
+                        }
+                        None => source.history(profile, &Query::default()),
+                    },
+                )
+            }),
+            bookmarks: profile.bookmarks.as_ref().map(|path| {
+                report_file(
+                    path,
+                    profile.locked,
+                    tables,
+                    bookmarks::queries_for(engine),
+                    || source.bookmarks(profile, &Query::default()),
+                )
+            }),
+        })
+        .collect()
+}
+
+/// Describe one data file, opening it as SQLite when it is a database
+fn report_file<F>(
+    path: &Path,
+    locked: Option<bool>,
+    tables: &[&str],
+    queries: Option<&QuerySet>,
+    read: F,
+) -> FileReport
+where
+    F: FnOnce() -> Result<Vec<SearchResult>, Box<dyn Error>>,
+{
+    let mut report = FileReport {
+        path: path.to_path_buf(),
+        schema_version: None,
+        query: None,
+        row_counts: BTreeMap::new(),
+        entries: None,
+        damage: Vec::new(),
+        recovered: false,
+        error: None,
+    };
+
+    if is_sqlite(path) {
+        if let Err(e) = inspect_db(path, locked, tables, queries, &mut report) {
+            report.error = Some(e.to_string());
+        }
+    }
+
+    match read() {
+        Ok(entries) => {
+            report.entries = Some(entries.len());
+            report.recovered = entries.iter().any(|entry| entry.recovered);
+        }
+        Err(e) => {
+            report.error.get_or_insert(e.to_string());
+        }
+    }
+
+    report
+}
+
+/// Check for the SQLite file header
+fn is_sqlite(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok()
+        && &header == b"SQLite format 3\0"
+}
+
+/// Read the schema version, query variant and row counts of a database
+fn inspect_db(
+    path: &Path,
+    locked: Option<bool>,
+    tables: &[&str],
+    queries: Option<&QuerySet>,
+    report: &mut FileReport,
+) -> Result<(), Box<dyn Error>> {
+    let (_temp_file, conn) = open_browser_db(path, locked)?;
+
+    // Damage is worth reporting even when nothing else can be read
+    let mut stmt = conn.prepare("PRAGMA quick_check(10)")?;
+    report.damage = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .filter_map(|result| result.ok())
+        .flat_map(|result| result.lines().map(String::from).collect::<Vec<_>>())
+        .filter(|line| line != "ok" && !line.starts_with("***"))
+        .collect();
+
+    report.schema_version = schema_version(&conn);
+    report.query = queries
+        .and_then(|queries| queries.pick(&conn))
+        .map(String::from);
+
+    for table in tables {
+        let count = conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| {
+            row.get(0)
+        });
+        if let Ok(count) = count {
+            report.row_counts.insert(table.to_string(), count);
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the report as JSON or as a human readable table
+pub fn run(json: bool) -> Result<(), Box<dyn Error>> {
+    let reports = diagnose();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+        return Ok(());
+    }
+
+    for report in &reports {
+        let flag = match &report.env_var {
+            Some(var) => format!("{}={}", var, if report.enabled { 1 } else { 0 }),
+            None => "config".to_string(),
+        };
+        let state = if report.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        };
+        let running = match report.running {
+            Some(true) => ", running",
+            Some(false) => ", not running",
+            None => "",
+        };
+        println!("{:<28} {:<24} {}{}", report.name, flag, state, running);
+
+        for location in &report.locations {
+            let exists = if location.exists { "found" } else { "missing" };
+            println!(
+                "  {:<8} {:<8} {}",
+                location.install,
+                exists,
+                location.path.display()
+            );
+
+            for profile in &location.profiles {
+                let mut notes = Vec::new();
+                notes.extend(profile.account.as_deref());
+                if profile.is_default {
+                    notes.push("default");
+                }
+                if !profile.selected {
+                    notes.push("excluded");
+                }
+                if profile.locked == Some(true) {
+                    notes.push("locked");
+                }
+                let notes = if notes.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", notes.join(", "))
+                };
+                println!("    profile {}{}", profile.name, notes);
+
+                let files = [
+                    ("history", &profile.history),
+                    ("bookmarks", &profile.bookmarks),
+                ];
+                for (kind, file) in files {
+                    let Some(file) = file else {
+                        println!("      {:<9} -", kind);
+                        continue;
+                    };
+                    println!("      {:<9} {}", kind, file.path.display());
+
+                    let mut details = Vec::new();
+                    if let Some(version) = file.schema_version {
+                        details.push(format!("schema {}", version));
+                    }
+                    if let Some(query) = &file.query {
+                        details.push(format!("{} query", query));
+                    }
+                    for (table, count) in &file.row_counts {
+                        details.push(format!("{}={}", table, count));
+                    }
+                    if let Some(entries) = file.entries {
+                        details.push(format!("{} entries read", entries));
+                    }
+                    if file.recovered {
+                        details.push("recovered from damage".to_string());
+                    }
+                    if let Some(error) = &file.error {
+                        details.push(format!("error: {}", error));
+                    }
+                    if !details.is_empty() {
+                        println!("                {}", details.join(", "));
+                    }
+                    for damage in &file.damage {
+                        println!("                damaged: {}", damage);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
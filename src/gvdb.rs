@@ -0,0 +1,524 @@
+//! Minimal reader for GVDB files (the GVariant database format used by
+//! GNOME, e.g. Epiphany's `bookmarks.gvdb`).
+//!
+//! Defines:
+//! - `Table` to walk the hash tables of a little-endian GVDB file
+//! - `Item` for the entries of a table (values or nested tables)
+//! - `Value` and `parse_variant` to decode serialized GVariant values
+//! - `GvdbError` for malformed input.
+
+use std::error::Error as StdError;
+use std::fmt;
+
+/// Errors raised while reading a GVDB file
+#[derive(Debug)]
+pub enum GvdbError {
+    /// The file does not start with the GVDB signature
+    BadSignature,
+    /// A pointer or value runs past the end of the data
+    Truncated,
+    /// A GVariant type string we cannot decode
+    UnsupportedType(String),
+    /// The parents of a key lead back to it
+    ParentCycle,
+    /// Types or values nest deeper than `MAX_DEPTH`
+    TooDeep,
+}
+
+impl StdError for GvdbError {}
+
+impl fmt::Display for GvdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GvdbError::BadSignature => write!(f, "Not a GVDB file"),
+            GvdbError::Truncated => write!(f, "GVDB data is truncated"),
+            GvdbError::UnsupportedType(ty) => write!(f, "Unsupported GVariant type '{}'", ty),
+            GvdbError::ParentCycle => write!(f, "GVDB key has a cyclic parent chain"),
+            GvdbError::TooDeep => write!(f, "GVariant nests deeper than {} levels", MAX_DEPTH),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, GvdbError>;
+
+const SIGNATURE: &[u8; 8] = b"GVariant";
+const HEADER_SIZE: usize = 24;
+const HASH_ITEM_SIZE: usize = 24;
+const NO_PARENT: u32 = u32::MAX;
+/// Deepest nesting of arrays, tuples and variants decoded, so a damaged
+/// file cannot overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// Read a little-endian u32 at `offset`
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data.get(offset..offset + 4).ok_or(GvdbError::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Resolve a `(start, end)` pointer stored in `source` at `offset` against the whole file
+fn read_pointer<'a>(file: &'a [u8], source: &[u8], offset: usize) -> Result<&'a [u8]> {
+    let start = read_u32(source, offset)? as usize;
+    let end = read_u32(source, offset + 4)? as usize;
+    file.get(start..end).ok_or(GvdbError::Truncated)
+}
+
+/// One hash table of a GVDB file
+pub struct Table<'a> {
+    file: &'a [u8],
+    items: &'a [u8],
+}
+
+/// An entry of a hash table
+pub enum Item<'a> {
+    /// A serialized GVariant of type `v`, see `parse_variant`
+    Value(&'a [u8]),
+    /// A nested hash table
+    Table(Table<'a>),
+    /// Any other entry, such as the lists GVDB uses for paths
+    Other,
+}
+
+impl<'a> Table<'a> {
+    /// Open the root table of a GVDB file
+    pub fn root(file: &'a [u8]) -> Result<Table<'a>> {
+        if file.len() < HEADER_SIZE || &file[..8] != SIGNATURE {
+            return Err(GvdbError::BadSignature);
+        }
+        Table::at(file, read_pointer(file, file, 16)?)
+    }
+
+    /// Parse the hash table stored in `table`
+    fn at(file: &'a [u8], table: &'a [u8]) -> Result<Table<'a>> {
+        // The top 5 bits of the bloom word count hold the bloom shift
+        let n_bloom_words = (read_u32(table, 0)? & ((1 << 27) - 1)) as usize;
+        let n_buckets = read_u32(table, 4)? as usize;
+        let items_start = 8 + 4 * (n_bloom_words + n_buckets);
+        let items = table.get(items_start..).ok_or(GvdbError::Truncated)?;
+        Ok(Table { file, items })
+    }
+
+    /// Number of entries in the table
+    fn len(&self) -> usize {
+        self.items.len() / HASH_ITEM_SIZE
+    }
+
+    /// Full key of the entry at `index`, following its parent chain.
+    ///
+    /// A chain longer than the table must loop, so it is rejected rather
+    /// than followed forever.
+    fn key(&self, index: usize) -> Result<String> {
+        let mut parts = Vec::new();
+        let mut current = index;
+        loop {
+            if parts.len() > self.len() {
+                return Err(GvdbError::ParentCycle);
+            }
+
+            let item = current * HASH_ITEM_SIZE;
+            let parent = read_u32(self.items, item + 4)?;
+            let key_start = read_u32(self.items, item + 8)? as usize;
+            let key_size = self
+                .items
+                .get(item + 12..item + 14)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+                .ok_or(GvdbError::Truncated)?;
+            let key = self
+                .file
+                .get(key_start..key_start + key_size)
+                .ok_or(GvdbError::Truncated)?;
+            parts.push(String::from_utf8_lossy(key));
+
+            if parent == NO_PARENT || parent as usize >= self.len() {
+                break;
+            }
+            current = parent as usize;
+        }
+
+        Ok(parts.into_iter().rev().collect())
+    }
+
+    /// Decode the entry at `index`
+    fn item(&self, index: usize) -> Result<Item<'a>> {
+        let item = index * HASH_ITEM_SIZE;
+        let kind = *self.items.get(item + 14).ok_or(GvdbError::Truncated)?;
+        let value = read_pointer(self.file, self.items, item + 16)?;
+        Ok(match kind {
+            b'v' => Item::Value(value),
+            b'H' => Item::Table(Table::at(self.file, value)?),
+            _ => Item::Other,
+        })
+    }
+
+    /// Every `(key, item)` pair of the table
+    pub fn entries(&self) -> Result<Vec<(String, Item<'a>)>> {
+        (0..self.len())
+            .map(|index| Ok((self.key(index)?, self.item(index)?)))
+            .collect()
+    }
+
+    /// Look up an entry by its full key
+    pub fn get(&self, key: &str) -> Result<Option<Item<'a>>> {
+        for index in 0..self.len() {
+            if self.key(index)? == key {
+                return self.item(index).map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A decoded GVariant value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Str(String),
+    Array(Vec<Value>),
+    Tuple(Vec<Value>),
+}
+
+impl Value {
+    /// Get the string, if this is one
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Get the elements, if this is an array
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed GVariant type string
+#[derive(Debug)]
+enum Type {
+    /// Fixed-size numbers and booleans, by their type character
+    Fixed(u8),
+    Str,
+    Variant,
+    Array(Box<Type>),
+    Tuple(Vec<Type>),
+}
+
+impl Type {
+    /// Parse one complete type from the front of `chars`, nested `depth`
+    /// levels deep
+    fn parse(chars: &mut std::slice::Iter<'_, u8>, depth: usize) -> Result<Type> {
+        if depth > MAX_DEPTH {
+            return Err(GvdbError::TooDeep);
+        }
+        let unsupported = |c: u8| GvdbError::UnsupportedType((c as char).to_string());
+        match chars.next().copied() {
+            Some(c @ (b'b' | b'y' | b'n' | b'q' | b'i' | b'u' | b'h' | b'x' | b't' | b'd')) => {
+                Ok(Type::Fixed(c))
+            }
+            Some(b's' | b'o' | b'g') => Ok(Type::Str),
+            Some(b'v') => Ok(Type::Variant),
+            Some(b'a') => Ok(Type::Array(Box::new(Type::parse(chars, depth + 1)?))),
+            Some(open @ (b'(' | b'{')) => {
+                let close = if open == b'(' { b')' } else { b'}' };
+                let mut members = Vec::new();
+                loop {
+                    if chars.as_slice().first() == Some(&close) {
+                        chars.next();
+                        return Ok(Type::Tuple(members));
+                    }
+                    members.push(Type::parse(chars, depth + 1)?);
+                }
+            }
+            Some(c) => Err(unsupported(c)),
+            None => Err(GvdbError::UnsupportedType(String::new())),
+        }
+    }
+
+    /// Alignment of the type in bytes
+    fn alignment(&self) -> usize {
+        match self {
+            Type::Fixed(b'b' | b'y') | Type::Str => 1,
+            Type::Fixed(b'n' | b'q') => 2,
+            Type::Fixed(b'i' | b'u' | b'h') => 4,
+            Type::Fixed(_) | Type::Variant => 8,
+            Type::Array(element) => element.alignment(),
+            Type::Tuple(members) => members.iter().map(Type::alignment).max().unwrap_or(1),
+        }
+    }
+
+    /// Serialized size of the type, if every value has the same size
+    fn fixed_size(&self) -> Option<usize> {
+        match self {
+            Type::Fixed(_) => Some(self.alignment()),
+            Type::Tuple(members) if members.is_empty() => Some(1),
+            Type::Tuple(members) => {
+                let mut size = 0;
+                for member in members {
+                    size = align(size, member.alignment()) + member.fixed_size()?;
+                }
+                Some(align(size, self.alignment()))
+            }
+            Type::Str | Type::Variant | Type::Array(_) => None,
+        }
+    }
+}
+
+/// Round `offset` up to a multiple of `alignment`
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+/// Size of the framing offsets in a container of `len` bytes
+fn offset_size(len: usize) -> usize {
+    match len {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x1_0000..=0xffff_ffff => 4,
+        _ => 8,
+    }
+}
+
+/// Read a little-endian framing offset
+fn read_offset(data: &[u8], at: usize, size: usize) -> Result<usize> {
+    let bytes = data.get(at..at + size).ok_or(GvdbError::Truncated)?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0usize, |acc, byte| (acc << 8) | *byte as usize))
+}
+
+/// Decode a serialized GVariant of type `v`: the value, a NUL byte, then its type string
+pub fn parse_variant(data: &[u8]) -> Result<Value> {
+    parse_nested_variant(data, 0)
+}
+
+/// Decode a variant nested `depth` levels deep in another value
+fn parse_nested_variant(data: &[u8], depth: usize) -> Result<Value> {
+    let split = data
+        .iter()
+        .rposition(|&b| b == 0)
+        .ok_or(GvdbError::Truncated)?;
+    let type_string = &data[split + 1..];
+    let ty = Type::parse(&mut type_string.iter(), depth)?;
+    decode(&ty, &data[..split], depth)
+}
+
+/// Decode `data` as a value of type `ty`, nested `depth` levels deep
+fn decode(ty: &Type, data: &[u8], depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(GvdbError::TooDeep);
+    }
+    match ty {
+        Type::Fixed(c) => {
+            let size = ty.fixed_size().unwrap_or(0);
+            let bytes = data.get(..size).ok_or(GvdbError::Truncated)?;
+            let mut buf = [0u8; 8];
+            buf[..size].copy_from_slice(bytes);
+            let raw = u64::from_le_bytes(buf);
+            Ok(match c {
+                b'b' => Value::Bool(raw != 0),
+                b'd' => Value::Double(f64::from_bits(raw)),
+                b'n' => Value::Int(raw as u16 as i16 as i64),
+                b'i' | b'h' => Value::Int(raw as u32 as i32 as i64),
+                _ => Value::Int(raw as i64),
+            })
+        }
+        Type::Str => {
+            let bytes = data.strip_suffix(&[0]).unwrap_or(data);
+            Ok(Value::Str(String::from_utf8_lossy(bytes).into_owned()))
+        }
+        Type::Variant => parse_nested_variant(data, depth + 1),
+        Type::Array(element) => decode_array(element, data, depth + 1),
+        Type::Tuple(members) => decode_tuple(members, data, depth + 1),
+    }
+}
+
+/// Decode an array, either fixed-width chunks or framed by trailing offsets
+fn decode_array(element: &Type, data: &[u8], depth: usize) -> Result<Value> {
+    if data.is_empty() {
+        return Ok(Value::Array(Vec::new()));
+    }
+
+    if let Some(size) = element.fixed_size() {
+        return data
+            .chunks(size)
+            .map(|chunk| decode(element, chunk, depth))
+            .collect::<Result<_>>()
+            .map(Value::Array);
+    }
+
+    // The last offset marks where the offset table itself begins
+    let size = offset_size(data.len());
+    let table_start = read_offset(data, data.len() - size, size)?;
+    let count = data.len().saturating_sub(table_start) / size;
+
+    let mut values = Vec::with_capacity(count);
+    let mut start = 0;
+    for index in 0..count {
+        let end = read_offset(data, table_start + index * size, size)?;
+        start = align(start, element.alignment());
+        let bytes = data.get(start..end).ok_or(GvdbError::Truncated)?;
+        values.push(decode(element, bytes, depth)?);
+        start = end;
+    }
+    Ok(Value::Array(values))
+}
+
+/// Decode a tuple, whose variable-size members (but the last) are framed
+/// by offsets stored backwards from the end
+fn decode_tuple(members: &[Type], data: &[u8], depth: usize) -> Result<Value> {
+    let size = offset_size(data.len());
+    let mut frame_end = data.len();
+    let mut start = 0;
+
+    let mut values = Vec::with_capacity(members.len());
+    for (index, member) in members.iter().enumerate() {
+        start = align(start, member.alignment());
+        let end = match member.fixed_size() {
+            Some(fixed) => start + fixed,
+            None if index == members.len() - 1 => frame_end,
+            None => {
+                frame_end = frame_end.checked_sub(size).ok_or(GvdbError::Truncated)?;
+                read_offset(data, frame_end, size)?
+            }
+        };
+        let bytes = data.get(start..end).ok_or(GvdbError::Truncated)?;
+        values.push(decode(member, bytes, depth)?);
+        start = end;
+    }
+    Ok(Value::Tuple(values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One entry of a fixture table: key, parent index, kind and value
+    type Entry<'a> = (&'a str, u32, u8, &'a [u8]);
+
+    /// Build a GVDB file whose root table holds `entries`, without any
+    /// bloom filter or buckets as the reader does not use them
+    fn gvdb_file(entries: &[Entry<'_>]) -> Vec<u8> {
+        let table_start = HEADER_SIZE;
+        let table_len = 8 + entries.len() * HASH_ITEM_SIZE;
+        let mut data_start = table_start + table_len;
+
+        let mut items = Vec::new();
+        let mut data: Vec<u8> = Vec::new();
+        for (key, parent, kind, value) in entries {
+            let key_start = data_start;
+            let value_start = key_start + key.len();
+            let value_end = value_start + value.len();
+            items.extend(0u32.to_le_bytes());
+            items.extend(parent.to_le_bytes());
+            items.extend((key_start as u32).to_le_bytes());
+            items.extend((key.len() as u16).to_le_bytes());
+            items.push(*kind);
+            items.push(0);
+            items.extend((value_start as u32).to_le_bytes());
+            items.extend((value_end as u32).to_le_bytes());
+            data.extend(key.as_bytes());
+            data.extend(*value);
+            data_start = value_end;
+        }
+
+        let mut file = SIGNATURE.to_vec();
+        file.extend(0u32.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend((table_start as u32).to_le_bytes());
+        file.extend(((table_start + table_len) as u32).to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend(items);
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn rejects_a_bad_signature() {
+        let file = b"NotGVDB!0000000000000000";
+        assert!(matches!(Table::root(file), Err(GvdbError::BadSignature)));
+    }
+
+    #[test]
+    fn joins_keys_along_their_parents() {
+        let file = gvdb_file(&[
+            ("bookmarks/", NO_PARENT, b'L', b""),
+            ("https://example.com", 0, b'v', b"Example\0\0s"),
+        ]);
+        let table = Table::root(&file).unwrap();
+
+        let keys: Vec<String> = table
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, ["bookmarks/", "bookmarks/https://example.com"]);
+
+        let Some(Item::Value(value)) = table.get("bookmarks/https://example.com").unwrap() else {
+            panic!("expected a value");
+        };
+        assert_eq!(parse_variant(value).unwrap(), Value::Str("Example".into()));
+    }
+
+    #[test]
+    fn rejects_cyclic_parents() {
+        let file = gvdb_file(&[("a", 1, b'v', b""), ("b", 0, b'v', b"")]);
+        let table = Table::root(&file).unwrap();
+        assert!(matches!(table.entries(), Err(GvdbError::ParentCycle)));
+
+        let file = gvdb_file(&[("a", 0, b'v', b"")]);
+        let table = Table::root(&file).unwrap();
+        assert!(matches!(table.get("a"), Err(GvdbError::ParentCycle)));
+    }
+
+    #[test]
+    fn rejects_truncated_pointers() {
+        let mut file = gvdb_file(&[("a", NO_PARENT, b'v', b"x\0s")]);
+        file.truncate(file.len() - 2);
+        let table = Table::root(&file).unwrap();
+        assert!(matches!(table.entries(), Err(GvdbError::Truncated)));
+    }
+
+    #[test]
+    fn decodes_framed_arrays_and_tuples() {
+        // `as` of "ab" and "c", framed by the end offsets 3 and 5
+        let value = parse_variant(b"ab\0c\0\x03\x05\0as").unwrap();
+        assert_eq!(
+            value,
+            Value::Array(vec![Value::Str("ab".into()), Value::Str("c".into())])
+        );
+
+        // `(xb)`: fixed-size members need no framing offsets
+        let value = parse_variant(b"\x01\0\0\0\0\0\0\0\0\0(xb)").unwrap();
+        assert_eq!(value, Value::Tuple(vec![Value::Int(1), Value::Bool(false)]));
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        // `aaa…s`, nested past the limit in the type string alone
+        let mut value = b"\0".to_vec();
+        value.extend(b"a".repeat(MAX_DEPTH + 1));
+        value.push(b's');
+        assert!(matches!(parse_variant(&value), Err(GvdbError::TooDeep)));
+
+        // Variants wrapped in variants, each with a type string of its own
+        let wrap = |levels: usize| {
+            let mut value = b"x\0s".to_vec();
+            for _ in 0..levels {
+                value.extend(b"\0v");
+            }
+            value
+        };
+        assert_eq!(parse_variant(&wrap(10)).unwrap(), Value::Str("x".into()));
+        assert!(matches!(
+            parse_variant(&wrap(10_000)),
+            Err(GvdbError::TooDeep)
+        ));
+    }
+}
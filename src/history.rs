@@ -1,69 +1,78 @@
 //! Searches browser history across enabled browsers.
 //!
-//! - `search(query: &str)` coordinates loading cached results,
-//!   reading each browser’s history via `get_chrome_history` /
-//!   `get_safari_history`, merging, deduplicating, sorting, and
-//!   limiting to MAX_RESULTS.
-//! - After gathering, it calls `fetch_favicons` to populate icons.
-use crate::browser::get_available_browsers;
-use crate::cache::get_cached_results;
-use crate::db::{create_temp_db_copy, query_chrome_history, query_safari_history};
-use crate::search::{filter_results, ResultSource, SearchResult};
+//! - `search(query: &Query)` coordinates pushing the parsed query (see
+//!   `query::Query`) down so SQLite only returns candidates, reading
+//!   each source’s history (see `source::BrowserSource`), which
+//!   in turn uses `get_chrome_history` /
+//!   `get_safari_history` / `get_firefox_history` /
+//!   `get_qutebrowser_history` / `get_epiphany_history` /
+//!   `get_falkon_history`, merging, deduplicating, sorting, and
+//!   limiting to the query's limit (MAX_RESULTS).
+//! - After gathering, it calls `fill_favicons` so each source populates icons.
+//! - `CHROME_HISTORY` / `SAFARI_HISTORY` / `FIREFOX_HISTORY` list the
+//!   queries for each schema version, and `queries_for` finds them by engine.
+//!   Those three engines are read incrementally through `store::query_history`,
+//!   or directly with `read_history_in_place` where nothing may be stored.
+use crate::browser::Engine;
+use crate::db::{
+    open_browser_db, query_or_salvage, query_pushed_down, Pushdown, QuerySet, QueryVariant, Salvage,
+};
+use crate::query::Query;
+use crate::search::{ResultSource, SearchResult};
+use crate::source::{fill_favicons, read_profiles, sources};
+use crate::store::{query_history, Visits};
 use crate::tie_break::break_a_tie;
-use crate::utils::fetch_favicons;
-use chrono::format::strftime;
 use jiff::{fmt::strtime, tz::TimeZone, Timestamp};
 use nucleo::{Matcher, Utf32Str};
-use rayon::prelude::*;
-use std::collections::{HashMap, HashSet};
+use rusqlite::types::{Type, Value};
+use rusqlite::{Error as SqliteError, Result as SqliteResult, Row};
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
 use std::path::Path;
 
 /// Searches browser history for the given query
-pub fn search(query: &str) -> Result<Vec<SearchResult>, Box<dyn Error>> {
-    let browsers = get_available_browsers();
-
-    // Get browser histories
-    let browser_histories: Vec<Vec<SearchResult>> = browsers
-        .par_iter()
-        .filter_map(|(browser, paths)| {
-            if let Some(history_path) = &paths.history {
-                let result = match browser {
-                    b if b.is_safari_like() => get_safari_history(history_path),
-                    b if b.is_firefox_like() => get_firefox_history(history_path),
-                    b if b.is_chrome_like() => get_chrome_history(history_path),
-                    _ => unreachable!("unsupported browser: {:?}", browser),
-                };
-
-                match result {
-                    Ok(results) => Some(results),
-                    Err(e) => {
-                        log::error!("Error searching {:?} history: {}", browser, e);
-                        None
-                    }
-                }
-            } else {
-                None
+pub fn search(query: &Query) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let sources = sources();
+
+    // The filters run in SQLite, so only candidates for the fuzzy matcher
+    // are loaded
+    let result_count = query.limit.unwrap_or(usize::MAX);
+
+    // Get browser histories, one profile at a time, keeping what passes
+    // the filters
+    let browser_histories = read_profiles(
+        &sources,
+        query,
+        "history",
+        |capabilities| capabilities.history,
+        |source, profile| {
+            if profile.locked == Some(true) {
+                log::warn!(
+                    "{} ({}) is open, its latest visits may not be saved yet",
+                    source.name(),
+                    profile.name
+                );
             }
-        })
-        .collect();
+            source.history(profile, query)
+        },
+    );
 
-    // Collect results
+    // Collect results, deduplicated by URL
     let mut all_results = Vec::new();
     let mut seen_urls = HashSet::new();
-
-    // Add new results from browsers
-    for results in browser_histories {
-        for result in results {
-            if seen_urls.insert(result.url.clone()) {
-                all_results.push(result);
-            }
+    for result in browser_histories {
+        if seen_urls.insert(result.url.clone()) {
+            all_results.push(result);
         }
     }
 
-    let result_count: usize = std::env::var("MAX_RESULTS")
-        .unwrap_or("30".to_string())
-        .parse()?;
+    // Filters alone list the latest matching visits
+    if query.text.is_empty() && query.has_filters() {
+        all_results.sort_by_key(|result| std::cmp::Reverse(result.last_visit));
+        all_results.truncate(result_count);
+        fill_favicons(&sources, &mut all_results)?;
+        return Ok(all_results);
+    }
 
     let config = nucleo::Config::DEFAULT;
 
@@ -71,14 +80,14 @@ pub fn search(query: &str) -> Result<Vec<SearchResult>, Box<dyn Error>> {
 
     // |1| Pre-segment your query once:
     let mut query_buf: Vec<char> = Vec::new();
-    let query_u32 = Utf32Str::new(query, &mut query_buf);
+    let query_u32 = Utf32Str::new(&query.text, &mut query_buf);
 
     // |2| Make a buffer to reuse for every title
     let mut title_buf: Vec<char> = Vec::new();
 
-    use std::collections::HashMap;
-
-    let limited_results: HashMap<u16, Vec<SearchResult>> = all_results
+    // Every candidate is scored, as SQLite returned them by recency rather
+    // than by how well they match
+    let score_levels: BTreeMap<u16, Vec<SearchResult>> = all_results
         .into_iter()
         .map(|item| {
             title_buf.clear(); // Clear any leftovers
@@ -88,19 +97,16 @@ pub fn search(query: &str) -> Result<Vec<SearchResult>, Box<dyn Error>> {
                 .unwrap_or(u16::MIN); // If no match is found, fallback to the smallest value of a u16 (0)
             (score, item)
         })
-        .take(result_count) // We take here because it's a local maximum, which means it reduces overhead while also maximizing results even if other components have no results to give.
-        .fold(HashMap::new(), |mut acc, (score, item)| {
+        .fold(BTreeMap::new(), |mut acc, (score, item)| {
             acc.entry(score).or_default().push(item);
             acc
         });
 
+    // Best matches first
     let mut final_results: Vec<SearchResult> = Vec::new();
-    for score_level in limited_results {
-        let score = score_level.0;
-        let items = score_level.1;
-
+    for (score, items) in score_levels.into_iter().rev() {
         // Ignore all results with a score equal to zero
-        if score <= 0 {
+        if score == 0 {
             continue;
         }
 
@@ -111,169 +117,348 @@ pub fn search(query: &str) -> Result<Vec<SearchResult>, Box<dyn Error>> {
             final_results.extend(break_a_tie(items, &matcher_instance));
         }
     }
+    final_results.truncate(result_count);
 
     // After all processing is finished, download the relevant favicons.
-    fetch_favicons(&mut final_results)?;
+    fill_favicons(&sources, &mut final_results)?;
 
     Ok(final_results)
 }
 
-/// Get Chrome-based browser history
-fn get_chrome_history(db_path: &Path) -> Result<Vec<SearchResult>, Box<dyn Error>> {
-    // Create a temporary copy of the database
-    let (_temp_file, conn) = create_temp_db_copy(db_path, None, None)?;
-
-    // Get the ignored domains from environment
-    let ignored_domains: Vec<String> = std::env::var("ignored_domains")
-        .unwrap_or_default()
-        .split(',')
-        .map(String::from)
-        .collect();
-
-    // Query the database
-    let sql = "SELECT DISTINCT urls.url, urls.title, urls.visit_count,
-         (urls.last_visit_time/1000000 + strftime('%s', '1601-01-01')) AS last_visit_time
-         FROM urls, visits
-         WHERE urls.id = visits.url AND
-         urls.title IS NOT NULL AND
-         urls.title != ''
-         ORDER BY last_visit_time DESC";
-
-    let results = query_chrome_history(&conn, sql, |row| {
-        let url: String = row.get(0)?;
-        let title: String = row.get(1)?;
-        let visit_count: i32 = row.get(2)?;
-        let last_visit: i64 = row.get(3)?;
-
-        // Format date based on user preference
-        let date_format = std::env::var("date_format").unwrap_or("%d.%m.%Y".to_string());
-        let dt = Timestamp::from_second(last_visit)
-            .expect("We know this is correct")
-            .in_tz("UTC")
-            .unwrap();
-        let formatted_date = strtime::format(&date_format, &dt).unwrap();
-
-        Ok(SearchResult {
-            title,
-            url,
-            subtitle: format!("Last visit: {} (Visits: {})", formatted_date, visit_count),
-            favicon: None,
-            source: ResultSource::History,
-            visit_count: Some(visit_count as u32),
-            last_visit: Some(
-                Timestamp::from_second(last_visit).expect("The timestamp should be correct"),
-            ),
-        })
-    })?;
-
-    // Filter out ignored domains
-    let filtered_results = results
-        .into_iter()
-        .filter(|result| {
-            !ignored_domains
-                .iter()
-                .any(|domain| result.url.contains(domain))
-        })
-        .collect();
-
-    Ok(filtered_results)
-}
-
-/// Get Safari history
-fn get_safari_history(db_path: &Path) -> Result<Vec<SearchResult>, Box<dyn Error>> {
-    // Create a temporary copy of the database
-    let (_temp_file, conn) = create_temp_db_copy(db_path, None, None)?;
-
-    // Query the database
-    let sql = "SELECT history_items.url, history_visits.title, history_items.visit_count,
-         (history_visits.visit_time + 978307200) AS last_visit_time
+/// Chromium `History` queries.
+///
+/// One query covers every `meta` version known, so its range is open-ended;
+/// a database it does not fit degrades to the minimal query.
+///
+/// Every history query returns `url`, `title`, `visit_count` and
+/// `last_visit_time` in Unix seconds, which the search's predicates use.
+/// A last visit of 0 means none is known.
+pub const CHROME_HISTORY: QuerySet = QuerySet {
+    // `urls` already aggregates the rows of `visits`, which is only checked
+    // so URLs known solely from typing or syncing stay out. URLs only loaded
+    // in subframes are left out (`urls.hidden`), as the History page does.
+    variants: &[QueryVariant {
+        name: "chromium",
+        versions: 1..=i64::MAX,
+        sql: "SELECT url, title, visit_count,
+         (CASE WHEN last_visit_time > 0
+          THEN last_visit_time/1000000 + strftime('%s', '1601-01-01')
+          ELSE 0 END) AS last_visit_time
+         FROM urls
+         WHERE title IS NOT NULL AND
+         title != '' AND
+         hidden = 0 AND
+         EXISTS (SELECT 1 FROM visits WHERE visits.url = urls.id)",
+    }],
+    minimal: "SELECT url, title, 0 AS visit_count, 0 AS last_visit_time FROM urls
+         WHERE title IS NOT NULL AND title != ''",
+    salvage: Some(Salvage {
+        table: "urls",
+        columns: "url, title, visit_count,
+         (CASE WHEN last_visit_time > 0
+          THEN last_visit_time/1000000 + strftime('%s', '1601-01-01')
+          ELSE 0 END) AS last_visit_time",
+    }),
+};
+
+/// Chromium visits, pointing at `urls`
+const CHROME_VISITS: Visits = Visits {
+    table: "visits",
+    entry: "url",
+    entries: "urls",
+};
+
+/// Safari `History.db` queries.
+///
+/// One query covers every `metadata` version known, so its range is
+/// open-ended; a database it does not fit degrades to the minimal query.
+pub const SAFARI_HISTORY: QuerySet = QuerySet {
+    // Titles live on the visits, so take the one of the latest visit
+    variants: &[QueryVariant {
+        name: "safari",
+        versions: 1..=i64::MAX,
+        sql: "SELECT history_items.url, history_visits.title, history_items.visit_count,
+         (MAX(history_visits.visit_time) + 978307200) AS last_visit_time
          FROM history_items
          INNER JOIN history_visits
          ON history_visits.history_item = history_items.id
          WHERE history_items.url IS NOT NULL AND
          history_visits.title IS NOT NULL AND
          history_items.url != ''
-         ORDER BY visit_count DESC";
-
-    let results = query_safari_history(&conn, sql, |row| {
-        let url: String = row.get(0)?;
-        let title: String = row.get(1)?;
-        let visit_count: i32 = row.get(2)?;
-        let last_visit_f: f64 = row.get(3)?;
-        let last_visit: i64 = last_visit_f as i64;
-
-        // Format date based on user preference
-        let date_format = std::env::var("date_format").unwrap_or("%d.%m.%Y".to_string());
-        let dt = Timestamp::from_second(last_visit)
-            .expect("We know this is correct")
-            .in_tz("UTC")
-            .unwrap();
-        let formatted_date = strtime::format(&date_format, &dt).unwrap();
-
-        Ok(SearchResult {
-            title,
-            url,
-            subtitle: format!("Last visit: {} (Visits: {})", formatted_date, visit_count),
-            favicon: None,
-            source: ResultSource::History,
-            visit_count: Some(visit_count as u32),
-            last_visit: Some(
-                Timestamp::from_second(last_visit).expect("The timestamp should be correct"),
-            ),
-        })
-    })?;
+         GROUP BY history_items.id",
+    }],
+    // Without the visits, fall back to showing the URL
+    minimal: "SELECT url, url AS title, 0 AS visit_count, 0 AS last_visit_time
+         FROM history_items
+         WHERE url IS NOT NULL AND url != ''",
+    salvage: Some(Salvage {
+        table: "history_items",
+        columns: "url, url AS title, visit_count, 0 AS last_visit_time",
+    }),
+};
+
+/// Safari visits, pointing at `history_items`
+const SAFARI_VISITS: Visits = Visits {
+    table: "history_visits",
+    entry: "history_item",
+    entries: "history_items",
+};
+
+/// Firefox `places.sqlite` history queries, by `user_version`
+pub const FIREFOX_HISTORY: QuerySet = QuerySet {
+    // Places never visited, such as bookmarks, have no last visit date
+    variants: &[
+        QueryVariant {
+            name: "firefox",
+            versions: 9..=i64::MAX,
+            sql: r#"
+            SELECT
+                url,
+                title,
+                visit_count,
+                (last_visit_date/1000000) AS last_visit_time
+            FROM moz_places
+            WHERE
+                url   IS NOT NULL
+                AND title IS NOT NULL
+                AND url   != ''
+                AND last_visit_date IS NOT NULL
+        "#,
+        },
+        // Schema 7 (Firefox 3.0, which added `moz_places.frecency`) is the
+        // oldest places database; until schema 9 added
+        // `moz_places.last_visit_date` the last visit is read from the visits
+        QueryVariant {
+            name: "firefox-8",
+            versions: 7..=8,
+            sql: r#"
+            SELECT
+                p.url,
+                p.title,
+                p.visit_count,
+                (MAX(v.visit_date)/1000000) AS last_visit_time
+            FROM moz_places AS p
+            JOIN moz_historyvisits AS v ON v.place_id = p.id
+            WHERE
+                p.url   IS NOT NULL
+                AND p.title IS NOT NULL
+                AND p.url   != ''
+            GROUP BY p.id
+        "#,
+        },
+    ],
+    minimal: "SELECT url, title, 0 AS visit_count, 0 AS last_visit_time FROM moz_places
+         WHERE url IS NOT NULL AND title IS NOT NULL",
+    salvage: Some(Salvage {
+        table: "moz_places",
+        columns: "url, title, visit_count, (last_visit_date/1000000) AS last_visit_time",
+    }),
+};
+
+/// Firefox visits, pointing at `moz_places`
+const FIREFOX_VISITS: Visits = Visits {
+    table: "moz_historyvisits",
+    entry: "place_id",
+    entries: "moz_places",
+};
+
+/// History queries chosen by schema version for an engine, if it has any
+pub fn queries_for(engine: Engine) -> Option<&'static QuerySet> {
+    match engine {
+        Engine::Chromium => Some(&CHROME_HISTORY),
+        Engine::WebKit => Some(&SAFARI_HISTORY),
+        Engine::Gecko => Some(&FIREFOX_HISTORY),
+        _ => None,
+    }
+}
+
+/// Read every entry of a history database without syncing it into the
+/// store, for diagnostics that must not keep a copy of the history
+pub fn read_history_in_place(
+    db_path: &Path,
+    queries: &QuerySet,
+    locked: Option<bool>,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let (_temp_file, conn) = open_browser_db(db_path, locked)?;
+    let (mut results, recovered) =
+        query_or_salvage(&conn, queries, &Pushdown::default(), read_history_row)?;
+
+    for result in &mut results {
+        result.recovered = recovered;
+    }
+
+    Ok(results)
+}
+
+/// Get Chrome-based browser history
+pub fn get_chrome_history(
+    db_path: &Path,
+    query: &Query,
+    locked: Option<bool>,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let (mut results, recovered) = query_history(
+        db_path,
+        locked,
+        &CHROME_HISTORY,
+        &CHROME_VISITS,
+        &query.pushdown(),
+        read_history_row,
+    )?;
+
+    for result in &mut results {
+        result.recovered = recovered;
+    }
+
+    Ok(results)
+}
+
+/// Get Safari history
+pub fn get_safari_history(
+    db_path: &Path,
+    query: &Query,
+    locked: Option<bool>,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let (mut results, recovered) = query_history(
+        db_path,
+        locked,
+        &SAFARI_HISTORY,
+        &SAFARI_VISITS,
+        &query.pushdown(),
+        read_history_row,
+    )?;
+
+    for result in &mut results {
+        result.recovered = recovered;
+    }
 
     Ok(results)
 }
 
 /// Get Firefox history
-pub fn get_firefox_history(db_path: &Path) -> Result<Vec<SearchResult>, Box<dyn Error>> {
-    // copy locked DB out of the way
-    let (_tmpfile, conn) = create_temp_db_copy(db_path, None, None)?;
-
-    let sql = r#"
-        SELECT
-            moz_places.url,
-            moz_places.title,
-            moz_places.visit_count,
-            (moz_historyvisits.visit_date/1000000) AS last_visit_time
-        FROM moz_places
-        LEFT JOIN moz_historyvisits
-            ON moz_places.id = moz_historyvisits.place_id
-        WHERE
-            moz_places.url   IS NOT NULL
-            AND moz_places.title IS NOT NULL
-            AND moz_places.url   != ''
-        ORDER BY last_visit_time DESC
-    "#;
-
-    // Reusing the chrome opperation because of the overlap
-    let results = query_chrome_history(&conn, sql, |row| {
-        let url: String = row.get(0)?;
-        let title: String = row.get(1)?;
-        let visit_count: i32 = row.get(2)?;
-        let last_visit: i64 = row.get(3)?;
-
-        // format date by user‐configured strftime
-        let date_format = std::env::var("date_format").unwrap_or_else(|_| "%d.%m.%Y".into());
-        let dt = Timestamp::from_second(last_visit)
-            .expect("We know this is correct")
-            .in_tz("UTC")
-            .unwrap();
-        let formatted_date = strtime::format(&date_format, &dt).unwrap();
-
-        Ok(SearchResult {
-            title,
-            url,
-            subtitle: format!("Last visit: {} (Visits: {})", formatted_date, visit_count),
-            favicon: None,
-            source: ResultSource::History,
-            visit_count: Some(visit_count as u32),
-            last_visit: Some(
-                Timestamp::from_second(last_visit).expect("The timestamp should be correct"),
-            ),
-        })
-    })?;
+pub fn get_firefox_history(
+    db_path: &Path,
+    query: &Query,
+    locked: Option<bool>,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let (mut results, recovered) = query_history(
+        db_path,
+        locked,
+        &FIREFOX_HISTORY,
+        &FIREFOX_VISITS,
+        &query.pushdown(),
+        read_history_row,
+    )?;
+
+    for result in &mut results {
+        result.recovered = recovered;
+    }
 
     Ok(results)
 }
+
+/// Get qutebrowser history
+pub fn get_qutebrowser_history(
+    db_path: &Path,
+    query: &Query,
+    locked: Option<bool>,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let (_temp_file, conn) = open_browser_db(db_path, locked)?;
+
+    // Every visit is its own row, so fold them per URL. Redirects are
+    // recorded too but never shown by qutebrowser itself.
+    let sql = "SELECT url, title, COUNT(*) AS visit_count, MAX(atime) AS last_visit_time
+         FROM History
+         WHERE redirect = 0 AND
+         url != '' AND
+         title IS NOT NULL AND
+         title != ''
+         GROUP BY url";
+
+    let results = query_pushed_down(&conn, sql, &query.pushdown(), read_history_row)?;
+
+    Ok(results)
+}
+
+/// Get GNOME Web (Epiphany) history
+pub fn get_epiphany_history(
+    db_path: &Path,
+    query: &Query,
+    locked: Option<bool>,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let (_temp_file, conn) = open_browser_db(db_path, locked)?;
+
+    // `urls` already aggregates the rows of `visits`; times are in microseconds
+    let sql = "SELECT url, title, visit_count, (last_visit_time/1000000) AS last_visit_time
+         FROM urls
+         WHERE url IS NOT NULL AND
+         url != '' AND
+         title IS NOT NULL AND
+         title != ''";
+
+    let results = query_pushed_down(&conn, sql, &query.pushdown(), read_history_row)?;
+
+    Ok(results)
+}
+
+/// Get Falkon history
+pub fn get_falkon_history(
+    db_path: &Path,
+    query: &Query,
+    locked: Option<bool>,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    let (_temp_file, conn) = open_browser_db(db_path, locked)?;
+
+    // One row per URL; `date` is the last visit in milliseconds
+    let sql = "SELECT url, title, count AS visit_count, (date/1000) AS last_visit_time
+         FROM history
+         WHERE url IS NOT NULL AND
+         url != '' AND
+         title IS NOT NULL AND
+         title != ''";
+
+    let results = query_pushed_down(&conn, sql, &query.pushdown(), read_history_row)?;
+
+    Ok(results)
+}
+
+/// Turn one row of a history query into a result.
+///
+/// Times that are missing or out of range, as salvaged rows may hold,
+/// leave the last visit unknown instead of failing the search.
+fn read_history_row(row: &Row<'_>) -> SqliteResult<SearchResult> {
+    let url: String = row.get(0)?;
+    let title: String = row.get(1)?;
+    let visit_count: i64 = row.get(2)?;
+    let visit_count = u32::try_from(visit_count.max(0)).unwrap_or(u32::MAX);
+    // Queries without a time for the last visit return 0
+    let last_visit = match row.get::<_, Value>(3)? {
+        Value::Integer(time) if time > 0 => Timestamp::from_second(time).ok(),
+        Value::Real(time) if time > 0.0 => Timestamp::from_second(time as i64).ok(),
+        _ => None,
+    };
+
+    let subtitle = match last_visit {
+        Some(last_visit) => {
+            // Format date based on user preference
+            let date_format = std::env::var("date_format").unwrap_or("%d.%m.%Y".to_string());
+            let date = strtime::format(&date_format, &last_visit.to_zoned(TimeZone::UTC))
+                .map_err(|e| SqliteError::FromSqlConversionFailure(3, Type::Integer, e.into()))?;
+            format!("Last visit: {} (Visits: {})", date, visit_count)
+        }
+        None => format!("Visits: {}", visit_count),
+    };
+
+    Ok(SearchResult {
+        title,
+        url,
+        subtitle,
+        favicon: None,
+        source: ResultSource::History,
+        visit_count: Some(visit_count),
+        last_visit,
+        profile: None,
+        browser: None,
+        recovered: false,
+        source_index: None,
+    })
+}
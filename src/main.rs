@@ -1,23 +1,40 @@
 //! Entry point for the browser‐search Alfred workflow.
 //!
-//! - Parses CLI args: command (`bookmarks`/`history`/default) and query.
-//! - Dispatches to bookmarks::search, history::search, or both.
+//! - Parses CLI args: command (`bookmarks`/`history`/default) and query,
+//!   plus `--root <dir|archive>` to search another home directory.
+//! - Parses the query once and dispatches to bookmarks::search,
+//!   history::search, or both.
+//! - `doctor [--json]` reports what browser discovery found instead.
+//! - `tabs` lists the matching open tabs, whose argument `focus` takes to
+//!   bring that tab to the front.
 //! - Deduplicates combined results, then calls alfred::output_results.
 //! - Uses env_logger and prints execution time to debug.
 
 use std::env;
 use std::error::Error;
+use std::path::Path;
 use std::time::Instant;
 
-use browser::Browser;
+use query::Query;
 
 mod alfred;
 mod bookmarks;
 mod browser;
-mod cache;
+mod config;
 mod db;
+mod doctor;
+mod gvdb;
 mod history;
+mod mozlz4;
+mod profiles;
+mod query;
+mod registry;
+mod root;
+mod running;
 mod search;
+mod snapshot;
+mod source;
+mod store;
 mod tabs;
 mod tie_break;
 mod utils;
@@ -26,17 +43,53 @@ fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
     let start = Instant::now();
 
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    // Search an exported home, backup or archive instead of HOME. The root
+    // is kept alive until the end so unpacked archives stay on disk.
+    let _root = match args.iter().position(|arg| arg == "--root") {
+        Some(index) if index + 1 < args.len() => {
+            let path = args.remove(index + 1);
+            args.remove(index);
+            let root = root::HomeRoot::open(Path::new(&path))?;
+            browser::set_home_root(root.path().to_path_buf());
+            Some(root)
+        }
+        Some(_) => return Err("--root expects a directory or archive".into()),
+        None => None,
+    };
+
     let command = args.get(1).map(|s| s.as_str()).unwrap_or("");
-    let query = args.get(2).map(|s| s.as_str()).unwrap_or("");
+    let raw_query = args.get(2).map(|s| s.as_str()).unwrap_or("");
+
+    if command == "doctor" {
+        return doctor::run(raw_query == "--json");
+    }
+    if command == "focus" {
+        return tabs::focus(raw_query);
+    }
+
+    // Split the filters from the text, for both searches
+    let mut query = Query::parse(raw_query);
+    query.limit = Some(
+        env::var("MAX_RESULTS")
+            .unwrap_or("30".to_string())
+            .parse()?,
+    );
+
+    if command == "tabs" {
+        alfred::output_tabs(&tabs::search(&query))?;
+        log::debug!("Tab search completed in {:?}", start.elapsed());
+        return Ok(());
+    }
 
     // produce one combined Vec<SearchResult>
     let results = match command {
-        "bookmarks" => bookmarks::search(query)?,
-        "history" => history::search(query)?,
+        "bookmarks" => bookmarks::search(&query)?,
+        "history" => history::search(&query)?,
         _ => {
-            let mut b = bookmarks::search(query)?;
-            let h = history::search(query)?;
+            let mut b = bookmarks::search(&query)?;
+            let h = history::search(&query)?;
             b.extend(h);
             search::deduplicate(b)
         }
@@ -0,0 +1,105 @@
+//! Reader for Mozilla's mozlz4 files.
+//!
+//! Firefox and its forks (Zen, LibreWolf, ...) store session, bookmark
+//! backup and search engine files (`recovery.jsonlz4`, `*.jsonlz4`,
+//! `search.json.mozlz4`) as one LZ4 block behind an 8-byte `mozLz40\0`
+//! magic and the decompressed size as a little-endian `u32`.
+//!
+//! Defines:
+//! - `decompress` reading the container, as the Firefox bookmarks reader
+//!   does for `bookmarkbackups`, and `compress` writing one for tests.
+
+use std::error::Error;
+
+/// Magic number at the start of every mozlz4 file
+pub const MAGIC: &[u8; 8] = b"mozLz40\0";
+
+/// Length of the magic and the decompressed size
+const HEADER_LEN: usize = MAGIC.len() + 4;
+
+/// Most an LZ4 block can expand: a byte of match length stands for at most
+/// 255 bytes of output
+const MAX_RATIO: usize = 255;
+
+/// Decompress the contents of a mozlz4 file
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+        return Err("Not a mozlz4 file: missing the mozLz40 header".into());
+    }
+
+    let size = u32::from_le_bytes(data[MAGIC.len()..HEADER_LEN].try_into()?) as usize;
+    let block = &data[HEADER_LEN..];
+
+    // The buffer is allocated up front, so do not trust a damaged header
+    // with up to 4 GiB
+    if size > block.len().saturating_mul(MAX_RATIO) {
+        return Err(format!(
+            "Corrupt mozlz4 file: {} bytes cannot hold {} decompressed bytes",
+            block.len(),
+            size
+        )
+        .into());
+    }
+    let decompressed = lz4_flex::block::decompress(block, size)?;
+
+    // A short block means the file was cut off while being written
+    if decompressed.len() != size {
+        return Err(format!(
+            "Truncated mozlz4 file: expected {} bytes, got {}",
+            size,
+            decompressed.len()
+        )
+        .into());
+    }
+
+    Ok(decompressed)
+}
+
+/// Compress bytes into the mozlz4 container
+#[cfg(test)]
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let size = u32::try_from(data.len()).map_err(|_| "Too large for a mozlz4 file")?;
+
+    let mut compressed = Vec::with_capacity(HEADER_LEN + data.len() / 2);
+    compressed.extend_from_slice(MAGIC);
+    compressed.extend_from_slice(&size.to_le_bytes());
+    compressed.extend(lz4_flex::block::compress(data));
+    Ok(compressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_bytes() {
+        let data = b"mozlz4 mozlz4 mozlz4 mozlz4 mozlz4".repeat(20);
+        let compressed = compress(&data).unwrap();
+        assert!(compressed.starts_with(MAGIC));
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        assert_eq!(decompress(&compress(b"").unwrap()).unwrap(), b"");
+    }
+
+    #[test]
+    fn rejects_a_bad_magic() {
+        let mut compressed = compress(b"{}").unwrap();
+        compressed[0] = b'M';
+        assert!(decompress(&compressed).is_err());
+        assert!(decompress(b"mozLz40").is_err());
+    }
+
+    #[test]
+    fn rejects_a_truncated_block() {
+        let compressed = compress(&b"0123456789abcdef".repeat(64)).unwrap();
+        assert!(decompress(&compressed[..compressed.len() - 4]).is_err());
+        assert!(decompress(&compressed[..HEADER_LEN]).is_err());
+    }
+
+    #[test]
+    fn rejects_a_size_the_block_cannot_hold() {
+        let mut compressed = compress(b"{}").unwrap();
+        compressed[MAGIC.len()..HEADER_LEN].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decompress(&compressed).is_err());
+    }
+}
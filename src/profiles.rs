@@ -0,0 +1,367 @@
+//! Discovers the individual profiles inside a browser's data directory.
+//!
+//! Defines:
+//! - `Profile` struct holding a profile's display name and data files
+//! - `chromium_profiles` that reads `Local State` (`profile.info_cache`)
+//!   to enumerate every profile of a Chromium user-data directory, along
+//!   with the signed-in account and avatar from each profile's `Preferences`.
+//! - `gecko_profiles` that reads `profiles.ini` / `installs.ini` to
+//!   enumerate every Firefox-like profile, default first.
+//! - `qutebrowser_profile` for qutebrowser's data or config directory.
+//! - `falkon_profiles` that enumerates `profiles/*` of a Falkon config
+//!   directory, starting with its `startProfile`.
+//! - `is_profile_selected` honouring the include/exclude env lists.
+//! - `tag_source` to mark search results with the browser and profile they
+//!   came from.
+
+use crate::browser::Install;
+use crate::search::SearchResult;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A single browser profile and the data files found inside it
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// Display name of the profile, e.g. "Work" or "Person 1"
+    pub name: String,
+    /// Whether the browser launches this profile by default
+    pub is_default: bool,
+    /// The install flavour the profile was found in
+    pub install: Install,
+    /// Whether a running browser holds the profile open, so its data may be
+    /// stale; `None` where this cannot be told
+    pub locked: Option<bool>,
+    /// Signed-in account, e.g. "me@corp.com" (Chromium only)
+    pub account: Option<String>,
+    /// Index of the built-in avatar picked for the profile (Chromium only)
+    pub avatar: Option<u32>,
+    pub history: Option<PathBuf>,
+    pub bookmarks: Option<PathBuf>,
+}
+
+impl Profile {
+    /// Build a profile from explicit file paths, keeping only those that exist.
+    pub fn new(name: impl Into<String>, history: PathBuf, bookmarks: PathBuf) -> Self {
+        Profile {
+            name: name.into(),
+            is_default: false,
+            install: Install::Native,
+            locked: None,
+            account: None,
+            avatar: None,
+            history: history.exists().then_some(history),
+            bookmarks: bookmarks.exists().then_some(bookmarks),
+        }
+    }
+
+    /// Name used to tag results, noting the account and sandboxed installs,
+    /// e.g. "Work (me@corp.com, Flatpak)"
+    pub fn label(&self) -> String {
+        let mut notes: Vec<&str> = Vec::new();
+        notes.extend(self.account.as_deref());
+        if self.install != Install::Native {
+            notes.push(self.install.name());
+        }
+
+        if notes.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} ({})", self.name, notes.join(", "))
+        }
+    }
+
+    /// Returns true if the profile has at least one readable data file.
+    pub fn has_data(&self) -> bool {
+        self.history.is_some() || self.bookmarks.is_some()
+    }
+}
+
+/// Enumerate the profiles of a Chromium user-data directory.
+///
+/// Profiles are read from `Local State`. When that file is missing or lists
+/// nothing usable we fall back to `Default/`, and finally to the directory
+/// itself, which is where Opera keeps its single profile.
+pub fn chromium_profiles(user_data_dir: &Path) -> Vec<Profile> {
+    let mut profiles: Vec<Profile> = read_info_cache(user_data_dir)
+        .into_iter()
+        .map(|(dir_name, info)| chromium_profile(&dir_name, info, user_data_dir.join(&dir_name)))
+        .filter(Profile::has_data)
+        .collect();
+
+    if profiles.is_empty() {
+        let fallbacks = [user_data_dir.join("Default"), user_data_dir.to_path_buf()];
+        profiles.extend(
+            fallbacks
+                .into_iter()
+                .map(|dir| chromium_profile("Default", Value::Null, dir))
+                .find(Profile::has_data),
+        );
+    }
+
+    profiles
+}
+
+/// Build a Chromium profile rooted at `dir` from its `info_cache` entry,
+/// falling back to its own `Preferences` for anything the entry lacks
+fn chromium_profile(default_name: &str, info: Value, dir: PathBuf) -> Profile {
+    let preferences: Value = fs::read_to_string(dir.join("Preferences"))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    let text = |value: Option<&Value>| {
+        value
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    };
+
+    let name = text(info.get("name"))
+        .or_else(|| text(preferences.pointer("/profile/name")))
+        .unwrap_or_else(|| default_name.to_string());
+    let account = text(info.get("user_name"))
+        .or_else(|| text(preferences.pointer("/account_info/0/email")))
+        .or_else(|| text(info.get("gaia_name")))
+        .or_else(|| text(preferences.pointer("/account_info/0/full_name")));
+    // `avatar_icon` looks like "chrome://theme/IDR_PROFILE_AVATAR_26"
+    let avatar = text(info.get("avatar_icon"))
+        .and_then(|icon| icon.rsplit('_').next()?.parse().ok())
+        .or_else(|| {
+            preferences
+                .pointer("/profile/avatar_index")
+                .and_then(Value::as_u64)
+                .map(|index| index as u32)
+        });
+
+    let mut profile = Profile::new(name, dir.join("History"), dir.join("Bookmarks"));
+    profile.account = account;
+    profile.avatar = avatar;
+    profile
+}
+
+/// Read `(directory name, info_cache entry)` pairs from `Local State`, sorted by directory
+fn read_info_cache(user_data_dir: &Path) -> Vec<(String, Value)> {
+    let contents = match fs::read_to_string(user_data_dir.join("Local State")) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+
+    let local_state: Value = match serde_json::from_str(&contents) {
+        Ok(value) => value,
+        Err(e) => {
+            log::warn!("Unable to parse {:?}/Local State: {}", user_data_dir, e);
+            return Vec::new();
+        }
+    };
+
+    let mut entries: Vec<(String, Value)> = local_state
+        .pointer("/profile/info_cache")
+        .and_then(Value::as_object)
+        .map(|cache| {
+            cache
+                .iter()
+                .map(|(dir_name, info)| (dir_name.clone(), info.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+/// Enumerate the profiles of a Firefox-like browser from its data directory.
+///
+/// `profiles.ini` lists every named profile, while the `Default=` entries of
+/// `installs.ini` (or the `[Install…]` sections) name the profile each
+/// installation actually launches. Without a `profiles.ini` we fall back to
+/// scanning `Profiles/` for any directory holding a `places.sqlite`.
+pub fn gecko_profiles(data_dir: &Path) -> Vec<Profile> {
+    let sections = match fs::read_to_string(data_dir.join("profiles.ini")) {
+        Ok(contents) => parse_ini(&contents),
+        Err(_) => return scan_gecko_profiles(&data_dir.join("Profiles")),
+    };
+
+    // Paths launched by an installation, as written in the ini files
+    let mut install_defaults: Vec<String> = sections
+        .iter()
+        .filter(|(section, _)| section.starts_with("Install"))
+        .filter_map(|(_, keys)| keys.get("Default").cloned())
+        .collect();
+    if let Ok(contents) = fs::read_to_string(data_dir.join("installs.ini")) {
+        install_defaults.extend(
+            parse_ini(&contents)
+                .into_iter()
+                .filter_map(|(_, mut keys)| keys.remove("Default")),
+        );
+    }
+
+    let mut profiles: Vec<Profile> = sections
+        .iter()
+        .filter(|(section, _)| section.starts_with("Profile"))
+        .filter_map(|(_, keys)| {
+            let path = keys.get("Path")?;
+            let dir = if keys.get("IsRelative").map(String::as_str) == Some("0") {
+                PathBuf::from(path)
+            } else {
+                data_dir.join(path)
+            };
+            let name = keys.get("Name").cloned().unwrap_or_else(|| path.clone());
+
+            let mut profile = gecko_profile(name, &dir);
+            profile.is_default = if install_defaults.is_empty() {
+                keys.get("Default").map(String::as_str) == Some("1")
+            } else {
+                install_defaults.contains(path)
+            };
+            Some(profile)
+        })
+        .filter(Profile::has_data)
+        .collect();
+
+    // Search the default profile first
+    profiles.sort_by_key(|profile| !profile.is_default);
+    profiles
+}
+
+/// Scan a `Profiles` directory for every profile holding `places.sqlite`
+fn scan_gecko_profiles(profiles_dir: &Path) -> Vec<Profile> {
+    let mut profiles: Vec<Profile> = fs::read_dir(profiles_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| {
+            gecko_profile(
+                entry.file_name().to_string_lossy().to_string(),
+                &entry.path(),
+            )
+        })
+        .filter(Profile::has_data)
+        .collect();
+
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    profiles
+}
+
+/// Build a Firefox-like profile rooted at `dir`
+pub fn gecko_profile(name: String, dir: &Path) -> Profile {
+    // history _and_ bookmarks live in the same DB
+    let db = dir.join("places.sqlite");
+    Profile::new(name, db.clone(), db)
+}
+
+/// Build the qutebrowser profile found in one of its directories.
+///
+/// History lives in the data directory while `bookmarks/urls` and
+/// `quickmarks` live in the config directory; the bookmarks path points at
+/// that config directory so both lists can be read from it.
+pub fn qutebrowser_profile(dir: &Path) -> Profile {
+    let has_bookmarks =
+        dir.join("bookmarks").join("urls").is_file() || dir.join("quickmarks").is_file();
+    let bookmarks = if has_bookmarks {
+        dir.to_path_buf()
+    } else {
+        PathBuf::new()
+    };
+    Profile::new("Default", dir.join("history.sqlite"), bookmarks)
+}
+
+/// Enumerate the profiles of a Falkon config directory.
+///
+/// Every directory under `profiles/` is a profile; `profiles/profiles.ini`
+/// names the one Falkon starts with.
+pub fn falkon_profiles(config_dir: &Path) -> Vec<Profile> {
+    let profiles_dir = config_dir.join("profiles");
+    let start_profile = fs::read_to_string(profiles_dir.join("profiles.ini"))
+        .ok()
+        .and_then(|contents| {
+            parse_ini(&contents)
+                .into_iter()
+                .find(|(section, _)| section == "Profiles")
+                .and_then(|(_, mut keys)| keys.remove("startProfile"))
+        })
+        .unwrap_or_else(|| "default".to_string());
+
+    let mut profiles: Vec<Profile> = fs::read_dir(&profiles_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| {
+            let dir = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            let mut profile =
+                Profile::new(name, dir.join("browsedata.db"), dir.join("bookmarks.json"));
+            profile.is_default = profile.name == start_profile;
+            profile
+        })
+        .filter(Profile::has_data)
+        .collect();
+
+    // Search the start profile first
+    profiles.sort_by(|a, b| (!a.is_default, &a.name).cmp(&(!b.is_default, &b.name)));
+    profiles
+}
+
+/// Parse an ini file into its sections, keeping their order
+fn parse_ini(contents: &str) -> Vec<(String, HashMap<String, String>)> {
+    let mut sections: Vec<(String, HashMap<String, String>)> = Vec::new();
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((section.to_string(), HashMap::new()));
+        } else if let (Some((key, value)), Some((_, keys))) =
+            (line.split_once('='), sections.last_mut())
+        {
+            keys.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    sections
+}
+
+/// Check a profile against the `included_profiles` / `excluded_profiles`
+/// env lists, by name, account or label. An empty include list selects
+/// every profile.
+pub fn is_profile_selected(profile: &Profile) -> bool {
+    let list = |var: &str| -> Vec<String> {
+        std::env::var(var)
+            .unwrap_or_default()
+            .split(',')
+            .map(|entry| entry.trim().to_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect()
+    };
+
+    let keys: Vec<String> = [Some(&profile.name), profile.account.as_ref()]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .chain([profile.label()])
+        .map(|key| key.to_lowercase())
+        .collect();
+    let matches = |list: Vec<String>| keys.iter().any(|key| list.contains(key));
+
+    let included = list("included_profiles");
+    (included.is_empty() || matches(included)) && !matches(list("excluded_profiles"))
+}
+
+/// Tag every result with the source (by position and browser name) and
+/// profile it was read from
+pub fn tag_source(
+    mut results: Vec<SearchResult>,
+    source_index: usize,
+    browser: &str,
+    profile: &Profile,
+) -> Vec<SearchResult> {
+    let label = profile.label();
+    for result in &mut results {
+        result.source_index = Some(source_index);
+        result.browser = Some(browser.to_string());
+        result.profile = Some(label.clone());
+    }
+    results
+}
@@ -0,0 +1,359 @@
+//! Parses search queries into free text and filters.
+//!
+//! Defines:
+//! - `Query` holding the text scored against titles, the filters typed
+//!   alongside it (`site:`, `after:`, `before:`) and the `ignored_domains`
+//!   setting
+//! - `Query::parse` splitting the raw query typed in Alfred
+//! - `Query::pushdown` translating it into the `WHERE` clauses and `LIMIT`
+//!   the history readers run in SQLite, so only candidates are loaded
+//! - `Query::accepts` applying the same filters to a read result.
+
+use crate::db::{Pushdown, MATCH_FOLD};
+use crate::search::SearchResult;
+use crate::utils::get_domain;
+use jiff::civil::Date;
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+use nucleo::chars;
+use rusqlite::types::Value;
+
+/// Candidates read from each profile for every result shown, when the
+/// fuzzy matcher picks among them
+const CANDIDATES_PER_RESULT: usize = 50;
+
+/// A search query, split into text and filters
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// Free text, matched fuzzily against titles
+    pub text: String,
+    /// `site:` filters; a URL's host must be one of them or a subdomain
+    pub domains: Vec<String>,
+    /// `after:` date, inclusive
+    pub after: Option<Timestamp>,
+    /// `before:` date, exclusive
+    pub before: Option<Timestamp>,
+    /// Domains from the `ignored_domains` setting
+    pub ignored_domains: Vec<String>,
+    /// Most results to show; see `pushdown` for how many candidates are read
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    /// Parse a raw query such as `rust docs site:github.com after:2024-01-01`
+    pub fn parse(raw: &str) -> Self {
+        let mut query = Query {
+            ignored_domains: ignored_domains(),
+            ..Default::default()
+        };
+
+        let mut words = Vec::new();
+        for word in raw.split_whitespace() {
+            if let Some(domain) = word
+                .strip_prefix("site:")
+                .or_else(|| word.strip_prefix("domain:"))
+                .filter(|domain| !domain.is_empty())
+            {
+                query.domains.push(domain.to_lowercase());
+            } else if let Some(date) = word.strip_prefix("after:").and_then(parse_date) {
+                query.after = Some(date);
+            } else if let Some(date) = word.strip_prefix("before:").and_then(parse_date) {
+                query.before = Some(date);
+            } else {
+                words.push(word);
+            }
+        }
+        query.text = words.join(" ");
+
+        query
+    }
+
+    /// Returns true if the query narrows results beyond its text
+    pub fn has_filters(&self) -> bool {
+        !self.domains.is_empty() || self.after.is_some() || self.before.is_some()
+    }
+
+    /// Translate the query into predicates on the columns every history
+    /// query returns: `url`, `title` and `last_visit_time` in seconds.
+    ///
+    /// The text becomes a `LIKE` pattern with its characters in order,
+    /// matched against the title as the fuzzy matcher folds it (see
+    /// `MATCH_FOLD`), so every title the matcher accepts also matches.
+    ///
+    /// SQLite returns the latest rows first, so the limit only holds as it
+    /// is without text. A loose pattern lets many weak matches through, and
+    /// cutting them at the limit could drop an older, better match before
+    /// it is scored. With text, each profile reads up to
+    /// `CANDIDATES_PER_RESULT` candidates per result instead: a strong match
+    /// older than that many recent weak ones is still lost, in exchange for
+    /// bounding what is scored on large histories.
+    pub fn pushdown(&self) -> Pushdown {
+        let limit = match self.text.is_empty() {
+            true => self.limit,
+            false => self
+                .limit
+                .map(|limit| limit.saturating_mul(CANDIDATES_PER_RESULT)),
+        };
+        let mut pushdown = Pushdown {
+            limit,
+            ..Default::default()
+        };
+
+        if !self.text.is_empty() {
+            // The matcher takes the first character of each grapheme of
+            // the text too
+            let mut pattern = String::from("%");
+            for c in chars::graphemes(&self.text) {
+                pattern.push_str(&escape_like(&c.to_string()));
+                pattern.push('%');
+            }
+            pushdown.push(
+                &format!("{}(title) LIKE ? ESCAPE '\\'", MATCH_FOLD),
+                Value::Text(pattern),
+            );
+        }
+
+        if !self.domains.is_empty() {
+            let params: Vec<Value> = self
+                .domains
+                .iter()
+                .flat_map(|domain| host_patterns(domain))
+                .map(Value::Text)
+                .collect();
+            let clause = vec!["url LIKE ? ESCAPE '\\'"; params.len()].join(" OR ");
+            pushdown.push_all(&format!("({})", clause), params);
+        }
+
+        for domain in &self.ignored_domains {
+            pushdown.push(
+                "url NOT LIKE ? ESCAPE '\\'",
+                Value::Text(format!("%{}%", escape_like(domain))),
+            );
+        }
+
+        if let Some(after) = self.after {
+            pushdown.push("last_visit_time >= ?", Value::Integer(after.as_second()));
+        }
+        if let Some(before) = self.before {
+            pushdown.push("last_visit_time < ?", Value::Integer(before.as_second()));
+        }
+
+        pushdown
+    }
+
+    /// Returns true if a result passes the query's filters, leaving its
+    /// text to the matcher.
+    ///
+    /// The `LIKE` patterns of `pushdown` can also match a host in a path;
+    /// this checks the parsed host. A result without a last visit fails
+    /// `after:` and `before:`, as it does in SQLite.
+    pub fn accepts(&self, result: &SearchResult) -> bool {
+        if !self.domains.is_empty() {
+            let host = get_domain(&result.url).map(|host| host.to_lowercase());
+            let on_site = host.is_some_and(|host| {
+                self.domains.iter().any(|domain| {
+                    host == *domain
+                        || host
+                            .strip_suffix(domain.as_str())
+                            .is_some_and(|sub| sub.ends_with('.'))
+                })
+            });
+            if !on_site {
+                return false;
+            }
+        }
+
+        if self
+            .ignored_domains
+            .iter()
+            .any(|domain| result.url.to_lowercase().contains(&domain.to_lowercase()))
+        {
+            return false;
+        }
+
+        if self.after.is_some() || self.before.is_some() {
+            let Some(last_visit) = result.last_visit else {
+                return false;
+            };
+            if self.after.is_some_and(|after| last_visit < after)
+                || self.before.is_some_and(|before| last_visit >= before)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// `LIKE` patterns matching URLs whose host is `domain` or one of its
+/// subdomains, with or without a port or path
+fn host_patterns(domain: &str) -> Vec<String> {
+    let domain = escape_like(domain);
+    let mut patterns = Vec::new();
+    for before in ["://", "."] {
+        for after in ["/%", ":%", ""] {
+            patterns.push(format!("%{}{}{}", before, domain, after));
+        }
+    }
+    patterns
+}
+
+/// Domains to leave out, from the comma separated `ignored_domains` setting.
+///
+/// Blank entries are dropped, as every URL contains the empty string.
+fn ignored_domains() -> Vec<String> {
+    std::env::var("ignored_domains")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|domain| !domain.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Parse a `YYYY-MM-DD` date as the start of that day in local time
+fn parse_date(date: &str) -> Option<Timestamp> {
+    let parsed = date
+        .parse::<Date>()
+        .and_then(|date| date.to_zoned(TimeZone::system()));
+    match parsed {
+        Ok(zoned) => Some(zoned.timestamp()),
+        Err(e) => {
+            log::warn!("Ignoring date {:?}: {}", date, e);
+            None
+        }
+    }
+}
+
+/// Escape the wildcards of a `LIKE` pattern, using `\` as escape character
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn site(domain: &str) -> Query {
+        Query {
+            domains: vec![domain.to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn visit(url: &str) -> SearchResult {
+        SearchResult {
+            title: String::new(),
+            url: url.to_string(),
+            subtitle: String::new(),
+            favicon: None,
+            source: crate::search::ResultSource::History,
+            visit_count: None,
+            last_visit: None,
+            profile: None,
+            browser: None,
+            recovered: false,
+            source_index: None,
+        }
+    }
+
+    const URLS: [&str; 6] = [
+        "https://github.com/rust-lang",
+        "https://gist.github.com",
+        "http://github.com:8080/",
+        "https://notgithub.com/",
+        "https://example.com/?next=github.com/",
+        "https://example.com/mirror.github.com/",
+    ];
+
+    #[test]
+    fn site_matches_hosts_and_subdomains() {
+        let query = site("github.com");
+        let accepted: Vec<&str> = URLS
+            .into_iter()
+            .filter(|url| query.accepts(&visit(url)))
+            .collect();
+        assert_eq!(accepted, &URLS[..3]);
+    }
+
+    /// URLs of the rows a query's pushdown lets through in SQLite
+    fn pushed_down(query: &Query, rows: &[(&str, &str)]) -> Vec<String> {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE urls (url TEXT, title TEXT, last_visit_time INTEGER)")
+            .unwrap();
+        for (url, title) in rows {
+            conn.execute("INSERT INTO urls VALUES (?1, ?2, 0)", [url, title])
+                .unwrap();
+        }
+
+        let pushdown = query.pushdown();
+        let mut stmt = pushdown
+            .prepare(&conn, "SELECT url, title, last_visit_time FROM urls")
+            .unwrap();
+        let urls = stmt
+            .query_map(rusqlite::params_from_iter(&pushdown.params), |row| {
+                row.get(0)
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        urls
+    }
+
+    #[test]
+    fn site_pushdown_is_anchored_on_the_host() {
+        let rows: Vec<(&str, &str)> = URLS.iter().map(|url| (*url, "")).collect();
+
+        // Only the mirror path also looks like a subdomain, which `accepts`
+        // then leaves out
+        assert_eq!(
+            pushed_down(&site("github.com"), &rows),
+            [URLS[0], URLS[1], URLS[2], URLS[5]]
+        );
+    }
+
+    #[test]
+    fn text_pushdown_keeps_titles_the_matcher_folds() {
+        let query = Query {
+            text: "resume".to_string(),
+            ..Default::default()
+        };
+        let titles = ["Résumé tips", "RÉSUMÉ", "Re\u{301}sume\u{301}", "Rust docs"];
+        let rows: Vec<(&str, &str)> = titles.iter().map(|title| (*title, *title)).collect();
+
+        let mut matcher = nucleo::Matcher::new(nucleo::Config::DEFAULT);
+        let (mut needle, mut haystack) = (Vec::new(), Vec::new());
+        let needle = nucleo::Utf32Str::new(&query.text, &mut needle);
+        let matched: Vec<&str> = titles
+            .into_iter()
+            .filter(|title| {
+                let haystack = nucleo::Utf32Str::new(title, &mut haystack);
+                matcher.fuzzy_match(haystack, needle).is_some()
+            })
+            .collect();
+
+        assert_eq!(matched, &titles[..3]);
+        assert_eq!(pushed_down(&query, &rows), matched);
+    }
+
+    #[test]
+    fn limit_is_widened_for_fuzzy_text() {
+        let mut query = Query {
+            limit: Some(30),
+            ..site("github.com")
+        };
+        assert_eq!(query.pushdown().limit, Some(30));
+
+        query.text = "rust".to_string();
+        assert_eq!(query.pushdown().limit, Some(30 * CANDIDATES_PER_RESULT));
+    }
+}
@@ -0,0 +1,116 @@
+//! Remembers custom profile directories seen on running browsers.
+//!
+//! Defines:
+//! - `custom_profile_dirs` combining the directories running browsers were
+//!   launched with and, when `remember_profile_dirs` is enabled, those
+//!   remembered from earlier runs so they stay searchable after the
+//!   browser exits.
+//! - `remembered_profile_dirs` listing the same without saving the
+//!   registry, for diagnostics.
+//!
+//! The registry is a JSON list kept in the data directory next to the
+//! history store (see `store`), in `<data dir>/browserSearch/profile_dirs.json`.
+
+use crate::browser::{has_home_root, Browser};
+use crate::running::launched_profile_dirs;
+use crate::utils::get_env_bool;
+use dirs::data_dir;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// A remembered profile directory
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    /// Env var name of the browser, e.g. `chromium`
+    browser: String,
+    path: PathBuf,
+}
+
+/// Get the path of the registry file
+fn get_registry_file() -> Option<PathBuf> {
+    Some(data_dir()?.join("browserSearch").join("profile_dirs.json"))
+}
+
+/// Read the registry, treating a missing or invalid file as empty
+fn load_registry() -> Vec<Entry> {
+    let Some(path) = get_registry_file() else {
+        return Vec::new();
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            log::error!("Error parsing profile registry {:?}: {}", path, e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Write the registry back to disk
+fn save_registry(entries: &[Entry]) -> Result<(), Box<dyn Error>> {
+    let path = get_registry_file().ok_or("No data directory for the profile registry")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(entries)?)?;
+    Ok(())
+}
+
+/// Custom profile directories of running browsers, plus remembered ones
+pub fn custom_profile_dirs() -> Vec<(Browser, PathBuf)> {
+    profile_dirs(true)
+}
+
+/// Like `custom_profile_dirs`, but leaving the registry file as it is
+pub fn remembered_profile_dirs() -> Vec<(Browser, PathBuf)> {
+    profile_dirs(false)
+}
+
+/// Custom profile directories, saving newly seen ones to the registry if `save`
+fn profile_dirs(save: bool) -> Vec<(Browser, PathBuf)> {
+    let mut dirs = launched_profile_dirs();
+    if has_home_root() || !get_env_bool("remember_profile_dirs") {
+        return dirs;
+    }
+
+    // Forget directories that have since been deleted
+    let saved = load_registry();
+    let mut entries: Vec<Entry> = saved
+        .iter()
+        .filter(|entry| entry.path.exists())
+        .map(|entry| Entry {
+            browser: entry.browser.clone(),
+            path: entry.path.clone(),
+        })
+        .collect();
+
+    for (browser, path) in &dirs {
+        let entry = Entry {
+            browser: browser.env_var().to_string(),
+            path: path.clone(),
+        };
+        if !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+
+    if save && entries != saved {
+        if let Err(e) = save_registry(&entries) {
+            log::error!("Error saving profile registry: {}", e);
+        }
+    }
+
+    for entry in entries {
+        match Browser::from_env_var(&entry.browser) {
+            Some(browser) if !dirs.contains(&(browser, entry.path.clone())) => {
+                dirs.push((browser, entry.path))
+            }
+            Some(_) => {}
+            None => log::warn!("Unknown browser {:?} in profile registry", entry.browser),
+        }
+    }
+
+    dirs
+}
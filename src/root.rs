@@ -0,0 +1,88 @@
+//! Resolves an alternative home directory to search instead of `$HOME`.
+//!
+//! Defines:
+//! - `HomeRoot`, opened from an exported home or profile directory, or from
+//!   a `.zip` / `.tar` / `.tar.gz` archive unpacked to a temporary directory
+//!   that lives as long as the `HomeRoot`.
+
+use flate2::read::GzDecoder;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// A directory standing in for the user's home
+pub struct HomeRoot {
+    path: PathBuf,
+    /// Keeps an unpacked archive on disk until the search is done
+    _unpacked: Option<TempDir>,
+}
+
+impl HomeRoot {
+    /// Open a directory or unpack an archive to use as the home root
+    pub fn open(path: &Path) -> Result<HomeRoot, Box<dyn Error>> {
+        if path.is_dir() {
+            return Ok(HomeRoot {
+                path: path.to_path_buf(),
+                _unpacked: None,
+            });
+        }
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let dir = tempfile::Builder::new()
+            .prefix("browser_search_root")
+            .tempdir()?;
+
+        log::debug!("Unpacking {:?} into {:?}", path, dir.path());
+        if name.ends_with(".zip") {
+            zip::ZipArchive::new(File::open(path)?)?.extract(dir.path())?;
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            tar::Archive::new(GzDecoder::new(File::open(path)?)).unpack(dir.path())?;
+        } else if name.ends_with(".tar") {
+            tar::Archive::new(File::open(path)?).unpack(dir.path())?;
+        } else {
+            return Err(format!(
+                "Unsupported root {:?}: expected a directory, .zip, .tar or .tar.gz",
+                path
+            )
+            .into());
+        }
+
+        Ok(HomeRoot {
+            path: unwrap_single_dir(dir.path()),
+            _unpacked: Some(dir),
+        })
+    }
+
+    /// The directory to use as home
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Archives usually wrap the home in a folder (`alice/`, `home/alice/`), so
+/// descend while there is a single plain directory. Hidden directories and
+/// `Library` / `snap` are browser data anchors and are never descended into.
+fn unwrap_single_dir(dir: &Path) -> PathBuf {
+    let mut dir = dir.to_path_buf();
+    loop {
+        let entries: Vec<_> = match fs::read_dir(&dir) {
+            Ok(entries) => entries.flatten().collect(),
+            Err(_) => return dir,
+        };
+
+        match entries.as_slice() {
+            [entry] if entry.path().is_dir() => {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') || name == "Library" || name == "snap" {
+                    return dir;
+                }
+                dir = entry.path();
+            }
+            _ => return dir,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Detects running browsers and locked profiles on Linux.
+//!
+//! Defines:
+//! - `Process` / `processes` listing the running processes from `/proc`
+//! - `is_running` matching those processes against `Browser::process_names`
+//! - `is_profile_locked` reading Chromium's `SingletonLock` and Firefox's
+//!   `lock` symlinks, ignoring locks whose process is gone. The result
+//!   decides how `db::open_browser_db` reads the profile's databases.
+//! - `launched_profile_dirs` finding the custom profile directories running
+//!   browsers were started with (`--user-data-dir`, `-profile`).
+//!
+//! Detection is only meaningful for the live system, so every check answers
+//! `None` on other platforms and when searching another root.
+
+use crate::browser::{Browser, Engine, Install};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// A running process, as listed in `/proc`
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: u32,
+    /// Command line, starting with the executable
+    pub args: Vec<String>,
+}
+
+impl Process {
+    /// Returns true if the process runs one of the given executables
+    fn runs(&self, names: &[&str]) -> bool {
+        let mut executables = self.args.iter().take(1).collect::<Vec<_>>();
+        // Python browsers (qutebrowser) run as `python3 /usr/bin/qutebrowser`
+        if self
+            .args
+            .first()
+            .is_some_and(|exe| file_name(exe).starts_with("python"))
+        {
+            executables.extend(self.args.get(1));
+        }
+
+        executables.into_iter().any(|exe| {
+            names.iter().any(|name| {
+                if name.contains('/') {
+                    exe.ends_with(name)
+                } else {
+                    file_name(exe) == *name
+                }
+            })
+        })
+    }
+
+    /// Value of a command-line option given as `flag=value` or `flag value`
+    fn option(&self, flags: &[&str]) -> Option<&str> {
+        self.args
+            .iter()
+            .enumerate()
+            .skip(1)
+            .find_map(|(index, arg)| {
+                flags.iter().find_map(|flag| {
+                    if arg == flag {
+                        self.args.get(index + 1).map(String::as_str)
+                    } else {
+                        arg.strip_prefix(flag)?.strip_prefix('=')
+                    }
+                })
+            })
+    }
+
+    /// Resolve a path from the command line against the process's working directory
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return Some(path.to_path_buf());
+        }
+        let cwd = fs::read_link(format!("/proc/{}/cwd", self.pid)).ok()?;
+        Some(cwd.join(path))
+    }
+}
+
+/// Last component of a path-like string
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Returns true if detection can look at the running system
+fn can_detect() -> bool {
+    cfg!(target_os = "linux") && !crate::browser::has_home_root()
+}
+
+/// Every running process, read once from `/proc`
+pub fn processes() -> &'static [Process] {
+    static PROCESSES: OnceLock<Vec<Process>> = OnceLock::new();
+    PROCESSES.get_or_init(|| {
+        fs::read_dir("/proc")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let pid = entry.file_name().to_str()?.parse().ok()?;
+                let cmdline = fs::read(entry.path().join("cmdline")).ok()?;
+                let args: Vec<String> = cmdline
+                    .split(|&byte| byte == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                    .collect();
+                (!args.is_empty()).then_some(Process { pid, args })
+            })
+            .collect()
+    })
+}
+
+/// Check whether a browser has a running process.
+///
+/// Browsers without known process names, such as those from the config
+/// file or that do not run on this platform, cannot be detected.
+pub fn is_running(browser: &Browser) -> Option<bool> {
+    let names = browser.process_names();
+    if !can_detect() || names.is_empty() {
+        return None;
+    }
+
+    Some(processes().iter().any(|process| process.runs(names)))
+}
+
+/// Host name of the running system
+fn hostname() -> Option<String> {
+    static HOSTNAME: OnceLock<Option<String>> = OnceLock::new();
+    HOSTNAME
+        .get_or_init(|| {
+            fs::read_to_string("/proc/sys/kernel/hostname")
+                .ok()
+                .map(|name| name.trim().to_string())
+        })
+        .clone()
+}
+
+/// Check whether a profile is locked by a live browser process.
+///
+/// Chromium keeps `SingletonLock` in the user-data directory, while Firefox
+/// keeps `lock` in each profile directory. Both are symlinks whose target
+/// ends with the owning pid (`host-1234`, `127.0.1.1:+1234`). Firefox's
+/// `.parentlock` is left behind after exit, so it is not a sign of use.
+///
+/// A Chromium lock taken on another host, as with a shared home, cannot be
+/// checked against the local processes and is trusted, as Chromium does.
+/// Flatpak and Snap browsers record pids of their sandbox, so whether their
+/// locks are live is unknown, as is whether the profile of an engine
+/// without a lock file we check (qutebrowser, Epiphany, Falkon) is in use.
+pub fn is_profile_locked(
+    engine: Engine,
+    install: Install,
+    data_dir: &Path,
+    profile_dir: &Path,
+) -> Option<bool> {
+    if !can_detect() {
+        return None;
+    }
+
+    let lock = match engine {
+        Engine::Chromium => data_dir.join("SingletonLock"),
+        Engine::Gecko => profile_dir.join("lock"),
+        _ => return None,
+    };
+
+    let target = match fs::read_link(&lock) {
+        Ok(target) => target.to_string_lossy().into_owned(),
+        Err(_) => return Some(false),
+    };
+    let (owner, pid) = match target.rsplit_once(['-', '+']) {
+        Some((owner, pid)) => (owner, pid.parse::<u32>().ok()),
+        None => (target.as_str(), None),
+    };
+
+    if engine == Engine::Chromium && hostname().is_some_and(|host| host != owner) {
+        log::trace!("Lock {:?} -> {:?} is held by another host", lock, target);
+        return Some(true);
+    }
+    if install != Install::Native {
+        log::trace!("Lock {:?} -> {:?} names a sandboxed pid", lock, target);
+        return None;
+    }
+
+    // Without a pid we cannot tell a stale lock apart, so trust it
+    let locked = match pid {
+        Some(pid) => Path::new("/proc").join(pid.to_string()).exists(),
+        None => true,
+    };
+
+    log::trace!("Lock {:?} -> {:?}, live: {}", lock, target, locked);
+    Some(locked)
+}
+
+/// Custom profile directories running browsers were started with.
+///
+/// Chromium browsers take a whole user-data directory through
+/// `--user-data-dir`, Firefox-like browsers a single profile through
+/// `-profile` / `--profile`. Child processes repeat the flag, so each
+/// directory is listed once.
+pub fn launched_profile_dirs() -> Vec<(Browser, PathBuf)> {
+    if !can_detect() {
+        return Vec::new();
+    }
+
+    let mut dirs: Vec<(Browser, PathBuf)> = Vec::new();
+    for process in processes() {
+        let Some(browser) = Browser::BUILT_IN
+            .into_iter()
+            .find(|browser| process.runs(browser.process_names()))
+        else {
+            continue;
+        };
+
+        let flags: &[&str] = match browser.engine() {
+            Engine::Chromium => &["--user-data-dir"],
+            Engine::Gecko => &["-profile", "--profile"],
+            _ => continue,
+        };
+
+        let Some(dir) = process.option(flags).and_then(|dir| process.resolve(dir)) else {
+            continue;
+        };
+        if !dirs.contains(&(browser, dir.clone())) {
+            log::trace!("{:?} (pid {}) runs with {:?}", browser, process.pid, dir);
+            dirs.push((browser, dir));
+        }
+    }
+
+    dirs
+}
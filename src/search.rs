@@ -20,6 +20,26 @@ pub struct SearchResult {
     pub source: ResultSource,
     pub visit_count: Option<u32>,
     pub last_visit: Option<Timestamp>,
+    /// Label of the browser profile (and install flavour) the result was read from
+    pub profile: Option<String>,
+    /// Name of the browser the result was read from
+    pub browser: Option<String>,
+    /// Whether the result was salvaged from a damaged database
+    #[serde(default)]
+    pub recovered: bool,
+    /// Position in `source::sources` of the source the result was read from
+    #[serde(skip)]
+    pub source_index: Option<usize>,
+}
+
+impl SearchResult {
+    /// Where the result was read from, e.g. "Google Chrome — Work (me@corp.com)"
+    pub fn origin(&self) -> Option<String> {
+        match (&self.browser, &self.profile) {
+            (Some(browser), Some(profile)) => Some(format!("{} — {}", browser, profile)),
+            (browser, profile) => browser.clone().or_else(|| profile.clone()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -0,0 +1,395 @@
+//! Persistent snapshots of browser databases that cannot be read in place.
+//!
+//! Defines:
+//! - `Stamp` identifying one state of a source database by the mtime, size
+//!   and inode of the file and of its `-wal`
+//! - `open_snapshot` reusing the stored snapshot of a source when its stamp
+//!   still matches, or copying the source into a fresh one
+//! - `collect_garbage` removing superseded, unused and half-written snapshots.
+//!
+//! Snapshots live in `<cache dir>/browserSearch/snapshots`, named
+//! `<source key>-<stamp>.sqlite` where the key is a hash of the canonical
+//! source path. A snapshot is prepared under a temporary name and renamed
+//! into place, so a reader only ever sees complete files.
+
+use rusqlite::{Connection, OpenFlags};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+
+/// Snapshots not used for this long are removed
+const UNUSED_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Temporary files older than this were left by an interrupted copy
+const ABANDONED_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// State of one file, as far as it decides whether a copy is still current
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    /// Modification time in nanoseconds since the epoch
+    mtime: u128,
+    size: u64,
+    inode: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let mtime = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Some(FileStamp {
+            mtime,
+            size: metadata.len(),
+            inode,
+        })
+    }
+}
+
+/// State of a source database together with its `-wal`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    db: FileStamp,
+    wal: Option<FileStamp>,
+}
+
+impl Stamp {
+    /// Read the stamp of a database, failing when it does not exist
+    pub fn of(db_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let db = FileStamp::of(db_path).ok_or_else(|| format!("Cannot stat {:?}", db_path))?;
+        Ok(Stamp {
+            db,
+            wal: FileStamp::of(&sibling(db_path, "-wal")),
+        })
+    }
+
+    /// Stable hash of the stamp, used in the snapshot's file name
+    pub fn fingerprint(&self) -> u64 {
+        let mut bytes = Vec::new();
+        for file in std::iter::once(Some(self.db)).chain([self.wal]) {
+            match file {
+                Some(file) => {
+                    bytes.extend(file.mtime.to_le_bytes());
+                    bytes.extend(file.size.to_le_bytes());
+                    bytes.extend(file.inode.to_le_bytes());
+                }
+                None => bytes.push(0),
+            }
+        }
+        fnv1a(&bytes)
+    }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is stable across releases
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Path of a sibling file such as `History-wal`
+pub fn sibling(db_path: &Path, suffix: &str) -> PathBuf {
+    let mut name = db_path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Returns true if the `-wal` file holds frames not yet checkpointed into
+/// the main file.
+///
+/// A WAL keeps its size after a checkpoint, so its length says little.
+/// Frames only count while their salts match the WAL header, which a
+/// writer changes when it restarts the log. Whether those frames were
+/// copied back is tracked in the `-shm` index: its header records the last
+/// valid frame and its checkpoint info the last frame copied. Without a
+/// matching index, valid frames are assumed to be pending.
+pub fn has_pending_wal(db_path: &Path) -> bool {
+    let Ok(wal) = read_prefix(&sibling(db_path, "-wal"), WAL_HEADER + 24) else {
+        return false;
+    };
+    // The first frame's header repeats the salts at its bytes 8..16
+    if wal.len() < WAL_HEADER + 24 || wal[16..24] != wal[WAL_HEADER + 8..WAL_HEADER + 16] {
+        return false;
+    }
+
+    let Ok(shm) = read_prefix(&sibling(db_path, "-shm"), 100) else {
+        return true;
+    };
+    let u32_at = |at: usize| u32::from_ne_bytes(shm[at..at + 4].try_into().unwrap());
+    // Index header: version, ..., init flag at 12, last valid frame at 16, salts at 32
+    let current =
+        shm.len() == 100 && u32_at(0) == 3007000 && shm[12] == 1 && shm[32..40] == wal[16..24];
+    if !current {
+        return true;
+    }
+    // Checkpoint info follows the two copies of the index header
+    let (max_frame, backfilled) = (u32_at(16), u32_at(96));
+    max_frame > backfilled
+}
+
+/// Length of the WAL file header
+const WAL_HEADER: usize = 32;
+
+/// Read up to `len` bytes from the start of a file
+fn read_prefix(path: &Path, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut prefix = Vec::with_capacity(len);
+    File::open(path)?
+        .take(len as u64)
+        .read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
+
+/// Get the directory holding the snapshots
+fn get_store_dir() -> Option<PathBuf> {
+    let dir = dirs::cache_dir()?.join("browserSearch").join("snapshots");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir)
+}
+
+/// Open a snapshot of a database, reusing the stored one when the source is unchanged.
+///
+/// When the source keeps changing while it is copied, or there is no store,
+/// the copy is only kept in a temp file, which must be kept alive as long as
+/// the connection.
+pub fn open_snapshot(
+    db_path: &Path,
+) -> Result<(Option<NamedTempFile>, Connection), Box<dyn Error>> {
+    let Some(dir) = get_store_dir() else {
+        log::debug!("No snapshot store, copying {:?} to a temp file", db_path);
+        let (temp_file, _) = prepare(db_path, NamedTempFile::new()?)?;
+        let conn = Connection::open(temp_file.path())?;
+        return Ok((Some(temp_file), conn));
+    };
+
+    let key = format!(
+        "{:016x}",
+        fnv1a(fs::canonicalize(db_path)?.as_os_str().as_encoded_bytes())
+    );
+    let stamp = Stamp::of(db_path)?;
+    let path = dir.join(format!("{}-{:016x}.sqlite", key, stamp.fingerprint()));
+
+    // Another search may remove the stored snapshot at any moment, so it is
+    // opened rather than checked for, and copied afresh when it is gone
+    if let Some(conn) = reuse_snapshot(&path)? {
+        log::debug!("Reusing snapshot {:?} of {:?}", path, db_path);
+        return Ok((None, conn));
+    }
+
+    let temp_file = tempfile::Builder::new()
+        .prefix(".tmp")
+        .suffix(".sqlite")
+        .tempfile_in(&dir)?;
+    let (temp_file, copied) = prepare(db_path, temp_file)?;
+
+    // Only a copy of a settled source matches the stamp it would be stored under
+    let Some(copied) = copied else {
+        collect_garbage(&dir, &key, None);
+        let conn = Connection::open(temp_file.path())?;
+        return Ok((Some(temp_file), conn));
+    };
+
+    // Open the copy before it is stored, as the garbage collection of
+    // another search may remove it as soon as it has its final name
+    let path = dir.join(format!("{}-{:016x}.sqlite", key, copied.fingerprint()));
+    let conn = open_read_only(temp_file.path())?;
+    match temp_file.persist(&path) {
+        Ok(_) => {
+            log::debug!("Stored snapshot {:?} of {:?}", path, db_path);
+            collect_garbage(&dir, &key, Some(&path));
+            Ok((None, conn))
+        }
+        Err(e) => {
+            log::warn!("Error storing snapshot {:?}: {}", path, e.error);
+            Ok((Some(e.file), conn))
+        }
+    }
+}
+
+/// Open a stored snapshot if it exists, refreshing its mtime so garbage
+/// collection sees it is still in use
+fn reuse_snapshot(path: &Path) -> Result<Option<Connection>, Box<dyn Error>> {
+    match File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()))
+    {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => log::warn!("Error touching snapshot {:?}: {}", path, e),
+    }
+
+    match open_read_only(path) {
+        Ok(conn) => Ok(Some(conn)),
+        Err(e) if !path.exists() => {
+            log::debug!("Snapshot {:?} was removed while opening it: {}", path, e);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Open a stored snapshot, which is never written to
+fn open_read_only(path: &Path) -> Result<Connection, Box<dyn Error>> {
+    Ok(Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?)
+}
+
+/// Copy a database into the temp file and fold its `-wal` into it.
+///
+/// Returns the stamp of the source the copy matches, or `None` when the
+/// source kept changing and the copy may mix several of its states.
+fn prepare(
+    db_path: &Path,
+    temp_file: NamedTempFile,
+) -> Result<(NamedTempFile, Option<Stamp>), Box<dyn Error>> {
+    let copied = copy_with_wal(db_path, temp_file.path())?;
+
+    // Switching out of WAL mode checkpoints the copied `-wal` and removes
+    // it, leaving a single self-contained file that opens read-only
+    let conn = Connection::open(temp_file.path())?;
+    conn.query_row("PRAGMA journal_mode = DELETE", [], |row| {
+        row.get::<_, String>(0)
+    })?;
+    drop(conn);
+
+    Ok((temp_file, copied))
+}
+
+/// Copy a database together with its `-wal`, so the copy holds what the
+/// browser has not yet checkpointed into the main file.
+///
+/// The pair is copied again if either file changed meanwhile, as a
+/// checkpoint between the two copies would lose the frames it moved. The
+/// `-shm` index is not copied: it only caches the WAL's layout and SQLite
+/// rebuilds it from the copied `-wal`, whereas a stale copy could mislead it.
+fn copy_with_wal(db_path: &Path, copy_path: &Path) -> Result<Option<Stamp>, Box<dyn Error>> {
+    let wal_path = sibling(db_path, "-wal");
+    let wal_copy = sibling(copy_path, "-wal");
+
+    for _ in 0..3 {
+        let before = Stamp::of(db_path)?;
+
+        fs::copy(db_path, copy_path)?;
+        if has_pending_wal(db_path) {
+            fs::copy(&wal_path, &wal_copy)?;
+        } else if wal_copy.exists() {
+            fs::remove_file(&wal_copy)?;
+        }
+
+        if Stamp::of(db_path)? == before {
+            return Ok(Some(before));
+        }
+        log::debug!("{:?} changed while copying it, copying again", db_path);
+    }
+
+    log::debug!("{:?} keeps changing, using the last copy", db_path);
+    Ok(None)
+}
+
+/// Remove stale snapshots from the store.
+///
+/// Snapshots of the source `key` other than `current` were superseded by
+/// it. Snapshots of any source are removed once unused for a week, which
+/// also covers sources that were deleted, and temp files once abandoned.
+pub fn collect_garbage(dir: &Path, key: &str, current: Option<&Path>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let age = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+
+        let stale = if name.starts_with(".tmp") {
+            age > ABANDONED_AFTER
+        } else {
+            let superseded =
+                current.is_some_and(|current| name.starts_with(key) && path != current);
+            superseded || age > UNUSED_AFTER
+        };
+
+        if stale {
+            log::trace!("Removing stale snapshot {:?}", path);
+            if let Err(e) = fs::remove_file(&path) {
+                log::warn!("Error removing snapshot {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpointed_wal_has_no_pending_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("History");
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA wal_autocheckpoint = 0;
+             CREATE TABLE urls (url TEXT);
+             INSERT INTO urls VALUES ('https://example.com');",
+        )
+        .unwrap();
+        assert!(has_pending_wal(&db_path));
+
+        // The open connection keeps the WAL and its frames after the checkpoint
+        conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))
+            .unwrap();
+        assert!(fs::metadata(sibling(&db_path, "-wal")).unwrap().len() > 32);
+        assert!(!has_pending_wal(&db_path));
+
+        conn.execute("INSERT INTO urls VALUES ('https://example.org')", [])
+            .unwrap();
+        assert!(has_pending_wal(&db_path));
+    }
+
+    #[test]
+    fn missing_wal_has_no_pending_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!has_pending_wal(&dir.path().join("History")));
+    }
+
+    #[test]
+    fn removed_snapshots_are_not_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.sqlite");
+        assert!(reuse_snapshot(&path).unwrap().is_none());
+
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("CREATE TABLE urls (url TEXT)")
+            .unwrap();
+        let conn = reuse_snapshot(&path).unwrap().unwrap();
+
+        // An open snapshot stays readable once garbage collection removes it
+        fs::remove_file(&path).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM urls", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
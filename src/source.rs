@@ -0,0 +1,370 @@
+//! Pluggable browser data sources.
+//!
+//! Defines:
+//! - `BrowserSource` trait reading history, bookmarks, open tabs and
+//!   favicons for one installed browser and its profiles
+//! - `Capabilities` describing which of those a source provides
+//! - `EngineSource` reading any built-in browser: a single struct holding
+//!   the history and bookmarks reader functions `new` picks for its
+//!   `Engine`, plus `ArcSource` adding Arc's sidebar to its Chromium
+//!   bookmarks
+//! - `sources` listing the source of every enabled browser
+//! - `read_profiles` reading every profile of the sources in parallel and
+//!   applying the query's filters to whatever each source returned
+//! - `fill_favicons` letting each source decorate the results it produced,
+//!   and fetching the rest.
+
+use crate::bookmarks::{
+    search_arc_sidebar, search_chrome_bookmarks, search_epiphany_bookmarks,
+    search_falkon_bookmarks, search_firefox_bookmarks, search_qutebrowser_bookmarks,
+    search_safari_bookmarks,
+};
+use crate::browser::{get_available_browsers, Browser, Engine};
+use crate::history::{
+    get_chrome_history, get_epiphany_history, get_falkon_history, get_firefox_history,
+    get_qutebrowser_history, get_safari_history,
+};
+use crate::profiles::{tag_source, Profile};
+use crate::query::Query;
+use crate::search::SearchResult;
+use crate::tabs::{has_tab_script, search_tabs, Tab};
+use crate::utils::fetch_favicons;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// What a source is able to provide
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Capabilities {
+    pub history: bool,
+    pub bookmarks: bool,
+    /// Whether the source can list the browser's open tabs
+    pub tabs: bool,
+    /// Whether the source has favicons of its own; the others are fetched
+    pub favicons: bool,
+}
+
+/// A source of searchable browser data: one browser and its profiles
+pub trait BrowserSource: Send + Sync {
+    /// Display name of the browser, used to tag results
+    fn name(&self) -> &str;
+
+    /// Profiles the source reads from
+    fn profiles(&self) -> &[Profile];
+
+    /// What the source is able to provide
+    fn capabilities(&self) -> Capabilities;
+
+    /// Read the history of one profile, narrowed down by the query
+    fn history(
+        &self,
+        profile: &Profile,
+        query: &Query,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>>;
+
+    /// Read the bookmarks of one profile matching the query's text. The
+    /// filters may be applied too, but `read_profiles` applies them anyway.
+    fn bookmarks(
+        &self,
+        profile: &Profile,
+        query: &Query,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>>;
+
+    /// Read the open tabs of the browser matching the query's text
+    fn tabs(&self, _query: &Query) -> Result<Vec<Tab>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
+    /// Fill in favicons from the source's own data for results it produced
+    fn favicons(&self, _results: &mut [SearchResult]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// A built-in browser together with the profiles discovered for it
+pub struct Installed {
+    pub browser: Browser,
+    pub profiles: Vec<Profile>,
+    /// Whether the browser is running, `None` where this cannot be detected
+    pub running: Option<bool>,
+}
+
+/// Reads the history file of a profile, given whether the profile is locked
+type HistoryReader = fn(&Path, &Query, Option<bool>) -> Result<Vec<SearchResult>, Box<dyn Error>>;
+
+/// Reads the bookmarks file of a profile, given whether the profile is locked
+type BookmarksReader = fn(&Path, &str, Option<bool>) -> Result<Vec<SearchResult>, Box<dyn Error>>;
+
+/// A built-in browser of any engine, read by the reader functions picked
+/// for that engine rather than by a type of its own
+pub struct EngineSource {
+    installed: Installed,
+    capabilities: Capabilities,
+    history: HistoryReader,
+    bookmarks: BookmarksReader,
+}
+
+/// Arc: a Chromium browser whose pinned tabs live in `StorableSidebar.json`
+pub struct ArcSource(pub EngineSource);
+
+impl EngineSource {
+    /// Pick the readers of the browser's engine
+    pub fn new(installed: Installed) -> Self {
+        let (history, bookmarks): (HistoryReader, BookmarksReader) =
+            match installed.browser.engine() {
+                // SQLite `History` and JSON `Bookmarks`
+                Engine::Chromium => (get_chrome_history, |path, query, _| {
+                    search_chrome_bookmarks(path, query)
+                }),
+                // History and bookmarks both in `places.sqlite`
+                Engine::Gecko => (get_firefox_history, |path, query, locked| {
+                    search_firefox_bookmarks(path, query, locked)
+                }),
+                // SQLite `History.db` and `Bookmarks.plist`
+                Engine::WebKit => (get_safari_history, |path, query, _| {
+                    search_safari_bookmarks(path, query)
+                }),
+                // SQLite `history.sqlite` and plain-text bookmarks / quickmarks
+                Engine::Qutebrowser => (get_qutebrowser_history, |path, query, _| {
+                    search_qutebrowser_bookmarks(path, query)
+                }),
+                // SQLite `ephy-history.db` and GVDB `bookmarks.gvdb`
+                Engine::Epiphany => (get_epiphany_history, |path, query, _| {
+                    search_epiphany_bookmarks(path, query)
+                }),
+                // SQLite `browsedata.db` and JSON `bookmarks.json`
+                Engine::Falkon => (get_falkon_history, |path, query, _| {
+                    search_falkon_bookmarks(path, query)
+                }),
+            };
+
+        EngineSource {
+            capabilities: Capabilities {
+                history: true,
+                bookmarks: true,
+                tabs: has_tab_script(&installed.browser),
+                favicons: false,
+            },
+            installed,
+            history,
+            bookmarks,
+        }
+    }
+}
+
+/// Run a reader on an optional profile file, yielding nothing when it is missing
+fn read_with<F>(path: &Option<PathBuf>, reader: F) -> Result<Vec<SearchResult>, Box<dyn Error>>
+where
+    F: FnOnce(&Path) -> Result<Vec<SearchResult>, Box<dyn Error>>,
+{
+    match path {
+        Some(path) => reader(path),
+        None => Ok(Vec::new()),
+    }
+}
+
+impl BrowserSource for EngineSource {
+    fn name(&self) -> &str {
+        self.installed.browser.name()
+    }
+
+    fn profiles(&self) -> &[Profile] {
+        &self.installed.profiles
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn history(
+        &self,
+        profile: &Profile,
+        query: &Query,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        read_with(&profile.history, |path| {
+            (self.history)(path, query, profile.locked)
+        })
+    }
+
+    fn bookmarks(
+        &self,
+        profile: &Profile,
+        query: &Query,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        read_with(&profile.bookmarks, |path| {
+            (self.bookmarks)(path, &query.text, profile.locked)
+        })
+    }
+
+    fn tabs(&self, query: &Query) -> Result<Vec<Tab>, Box<dyn Error>> {
+        // A browser known to be closed has no tabs to ask for
+        if !self.capabilities.tabs || self.installed.running == Some(false) {
+            return Ok(Vec::new());
+        }
+        Ok(search_tabs(&self.installed.browser, &query.text)?)
+    }
+}
+
+impl BrowserSource for ArcSource {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn profiles(&self) -> &[Profile] {
+        self.0.profiles()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
+
+    fn history(
+        &self,
+        profile: &Profile,
+        query: &Query,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        self.0.history(profile, query)
+    }
+
+    fn bookmarks(
+        &self,
+        profile: &Profile,
+        query: &Query,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let mut results = self.0.bookmarks(profile, query)?;
+
+        // The sidebar sits next to `User Data`, above the profile directory
+        let profile_dir = profile
+            .history
+            .as_deref()
+            .or(profile.bookmarks.as_deref())
+            .and_then(Path::parent);
+        let sidebar = profile_dir
+            .and_then(Path::parent)
+            .and_then(Path::parent)
+            .map(|arc_dir| arc_dir.join("StorableSidebar.json"))
+            .filter(|sidebar| sidebar.is_file());
+        if let (Some(dir), Some(sidebar)) = (profile_dir.and_then(Path::file_name), sidebar) {
+            // A broken sidebar should not hide the bookmarks already read
+            match search_arc_sidebar(&sidebar, &dir.to_string_lossy(), &query.text) {
+                Ok(items) => results.extend(items),
+                Err(e) => log::error!("Error searching Arc sidebar {:?}: {}", sidebar, e),
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn tabs(&self, query: &Query) -> Result<Vec<Tab>, Box<dyn Error>> {
+        self.0.tabs(query)
+    }
+}
+
+/// Wrap an installed browser in the source for its engine
+pub fn source_for(installed: Installed) -> Arc<dyn BrowserSource> {
+    if installed.browser == Browser::Arc {
+        return Arc::new(ArcSource(EngineSource::new(installed)));
+    }
+    Arc::new(EngineSource::new(installed))
+}
+
+/// The source of every enabled browser
+pub fn sources() -> Vec<Arc<dyn BrowserSource>> {
+    get_available_browsers()
+        .into_iter()
+        .map(|(browser, paths)| {
+            source_for(Installed {
+                browser,
+                profiles: paths.profiles,
+                running: paths.running,
+            })
+        })
+        .collect()
+}
+
+/// Read every profile of the sources with a capability, in parallel.
+///
+/// Results are tagged with their source and profile, and only those passing
+/// the query's filters are kept, whatever a source already pushed down.
+pub fn read_profiles<F>(
+    sources: &[Arc<dyn BrowserSource>],
+    query: &Query,
+    what: &str,
+    wanted: fn(Capabilities) -> bool,
+    read: F,
+) -> Vec<SearchResult>
+where
+    F: Fn(&dyn BrowserSource, &Profile) -> Result<Vec<SearchResult>, Box<dyn Error>> + Sync,
+{
+    sources
+        .iter()
+        .enumerate()
+        .filter(|(_, source)| wanted(source.capabilities()))
+        .flat_map(|(index, source)| {
+            source
+                .profiles()
+                .iter()
+                .map(move |profile| (index, source, profile))
+        })
+        .collect::<Vec<_>>()
+        .par_iter()
+        .filter_map(
+            |(index, source, profile)| match read(source.as_ref(), profile) {
+                Ok(results) => Some(tag_source(results, *index, source.name(), profile)),
+                Err(e) => {
+                    log::error!(
+                        "Error searching {} ({}) {}: {}",
+                        source.name(),
+                        profile.name,
+                        what,
+                        e
+                    );
+                    None
+                }
+            },
+        )
+        .flatten()
+        .filter(|result| query.accepts(result))
+        .collect()
+}
+
+/// Fill in favicons, keeping the results' order.
+///
+/// Sources with favicons of their own fill in the results they produced;
+/// every other result is fetched in a single batch.
+pub fn fill_favicons(
+    sources: &[Arc<dyn BrowserSource>],
+    results: &mut [SearchResult],
+) -> Result<(), Box<dyn Error>> {
+    // Positions of the results each source produced
+    let mut positions: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (index, result) in results.iter().enumerate() {
+        if let Some(source) = result.source_index {
+            positions.entry(source).or_default().push(index);
+        }
+    }
+
+    for (source, indices) in positions {
+        let Some(source) = sources.get(source).filter(|s| s.capabilities().favicons) else {
+            continue;
+        };
+        let mut batch: Vec<SearchResult> = indices.iter().map(|&i| results[i].clone()).collect();
+        source.favicons(&mut batch)?;
+        for (index, result) in indices.into_iter().zip(batch) {
+            results[index] = result;
+        }
+    }
+
+    let missing: Vec<usize> = (0..results.len())
+        .filter(|&i| results[i].favicon.is_none())
+        .collect();
+    let mut batch: Vec<SearchResult> = missing.iter().map(|&i| results[i].clone()).collect();
+    fetch_favicons(&mut batch)?;
+    for (index, result) in missing.into_iter().zip(batch) {
+        results[index] = result;
+    }
+
+    Ok(())
+}
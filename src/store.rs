@@ -0,0 +1,537 @@
+//! Local store of browser history, kept up to date incrementally.
+//!
+//! Defines:
+//! - `query_history` answering a search from the store, after syncing the
+//!   profile's history database into it
+//! - `Visits` naming the append-only visits table of a history database
+//!   and how its rows point at the entries
+//! - `Mark` the high-water mark kept per history database: the stamp of
+//!   the file when last synced, its highest visit id, its number of visits
+//!   and the latest visit time read, and whether the entries were salvaged
+//!   from a damaged database.
+//!
+//! Visit tables are append-only but for deletions, so a sync reads only the
+//! entries with a visit beyond the mark's id. Their times cannot stand in
+//! for the ids, as visits synced from other devices keep the time they
+//! were made, however long ago. When visits were deleted (cleared
+//! history, expiry) the counts no longer add up and the profile is read
+//! again in full, which also forgets databases that have since disappeared.
+//! A running browser changes the stamp on every write, so a sync opens the
+//! database through `open_browser_db`: the delta is read in place
+//! (`mode=ro`) and the database is only snapshotted while it is locked.
+//! The store lives in `<data dir>/browserSearch/history.sqlite`.
+
+use crate::browser::has_home_root;
+use crate::db::{open_browser_db, query_or_salvage, Pushdown, QuerySet};
+use crate::snapshot::Stamp;
+use dirs::data_dir;
+use rusqlite::types::Value;
+use rusqlite::{
+    params, params_from_iter, Connection, OptionalExtension, Result as SqliteResult, Row,
+};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Columns of the store, in the order every history query returns them
+const ENTRIES: &str =
+    "SELECT url, title, visit_count, last_visit_time FROM entries WHERE source = ?1";
+
+/// The visits table of a history database, whose ids serve as the mark
+#[derive(Debug)]
+pub struct Visits {
+    pub table: &'static str,
+    /// Column of a visit holding the `id` of its entry
+    pub entry: &'static str,
+    /// Table of the entries, whose `url` the history queries return
+    pub entries: &'static str,
+}
+
+impl Visits {
+    /// Predicate on the queries' `url` keeping the entries with a visit
+    /// whose id is beyond `?`
+    fn newer_than(&self) -> String {
+        format!(
+            "url IN (SELECT {entries}.url FROM {visits}
+             JOIN {entries} ON {entries}.id = {visits}.{entry}
+             WHERE {visits}.id > ?)",
+            entries = self.entries,
+            visits = self.table,
+            entry = self.entry
+        )
+    }
+}
+
+/// How far a history database was synced into the store
+#[derive(Debug)]
+struct Mark {
+    /// Fingerprint of the database file (and `-wal`) at the last sync
+    stamp: String,
+    /// Query variant the entries were read with
+    query: String,
+    max_visit_id: i64,
+    visits: i64,
+    last_visit_time: i64,
+    /// Whether the entries were salvaged from a damaged database
+    recovered: bool,
+}
+
+/// Version of the store's tables; a store of another version is rebuilt
+const STORE_VERSION: i64 = 2;
+
+/// Get the path of the store
+fn get_store_file() -> Option<PathBuf> {
+    let dir = data_dir()?.join("browserSearch");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir.join("history.sqlite"))
+}
+
+/// Open the store, creating its tables on first use
+fn open_store() -> Result<Connection, Box<dyn Error>> {
+    let path = get_store_file().ok_or("No data directory for the history store")?;
+    let conn = Connection::open(path)?;
+
+    // Alfred runs a search per keystroke, so let readers overlap a sync
+    conn.query_row("PRAGMA journal_mode = WAL", [], |row| {
+        row.get::<_, String>(0)
+    })?;
+    create_tables(&conn)?;
+    Ok(conn)
+}
+
+/// Create the tables of the store, dropping those of another version
+fn create_tables(conn: &Connection) -> SqliteResult<()> {
+    // The store only caches the browsers' databases, so rebuild it on change
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version != STORE_VERSION {
+        conn.execute_batch(&format!(
+            "DROP TABLE IF EXISTS entries;
+             DROP TABLE IF EXISTS marks;
+             PRAGMA user_version = {};",
+            STORE_VERSION
+        ))?;
+    }
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS entries (
+             source TEXT NOT NULL,
+             url TEXT NOT NULL,
+             title TEXT NOT NULL,
+             visit_count INTEGER NOT NULL,
+             last_visit_time INTEGER NOT NULL,
+             PRIMARY KEY (source, url)
+         ) WITHOUT ROWID;
+         CREATE INDEX IF NOT EXISTS entries_by_time ON entries (source, last_visit_time);
+         CREATE TABLE IF NOT EXISTS marks (
+             source TEXT PRIMARY KEY,
+             stamp TEXT NOT NULL,
+             query TEXT NOT NULL,
+             max_visit_id INTEGER NOT NULL,
+             visits INTEGER NOT NULL,
+             last_visit_time INTEGER NOT NULL,
+             recovered INTEGER NOT NULL
+         );",
+    )
+}
+
+/// Search the history of one database through the store.
+///
+/// Returns the results and whether they were salvaged from a damaged
+/// database.
+/// `visits` names the append-only table of visits whose ids serve as the
+/// high-water mark. Without a store, or when searching another root whose
+/// paths do not last, the database is queried directly.
+pub fn query_history<F, T>(
+    db_path: &Path,
+    locked: Option<bool>,
+    queries: &QuerySet,
+    visits: &Visits,
+    pushdown: &Pushdown,
+    row_mapper: F,
+) -> Result<(Vec<T>, bool), Box<dyn Error>>
+where
+    F: FnMut(&Row<'_>) -> SqliteResult<T>,
+{
+    let store = if has_home_root() {
+        None
+    } else {
+        open_store()
+            .map_err(|e| log::warn!("Cannot open the history store: {}", e))
+            .ok()
+    };
+    let Some(mut store) = store else {
+        let (_temp_file, conn) = open_browser_db(db_path, locked)?;
+        return query_or_salvage(&conn, queries, pushdown, row_mapper);
+    };
+
+    let source = fs::canonicalize(db_path)?.to_string_lossy().into_owned();
+    let recovered = sync(&mut store, &source, db_path, locked, queries, visits)?;
+
+    let params = std::iter::once(Value::Text(source)).chain(pushdown.params.iter().cloned());
+    let mut stmt = pushdown.prepare(&store, ENTRIES)?;
+    let results = stmt
+        .query_map(params_from_iter(params), row_mapper)?
+        .collect::<SqliteResult<Vec<_>>>()?;
+
+    Ok((results, recovered))
+}
+
+/// Bring the entries of one database up to date in the store, returning
+/// whether they had to be salvaged
+fn sync(
+    store: &mut Connection,
+    source: &str,
+    db_path: &Path,
+    locked: Option<bool>,
+    queries: &QuerySet,
+    visits: &Visits,
+) -> Result<bool, Box<dyn Error>> {
+    let stamp = format!("{:016x}", Stamp::of(db_path)?.fingerprint());
+    let mark = load_mark(store, source)?;
+    if let Some(mark) = mark.as_ref().filter(|mark| mark.stamp == stamp) {
+        log::trace!("{:?} is unchanged since the last sync", db_path);
+        return Ok(mark.recovered);
+    }
+
+    let (_temp_file, conn) = open_browser_db(db_path, locked)?;
+    let query = queries.pick(&conn).ok_or("No query fits the schema")?;
+    // A damaged visits table cannot vouch for the mark, so read everything
+    let (max_visit_id, visit_count): (Option<i64>, i64) = conn
+        .query_row(
+            &format!("SELECT MAX(id), COUNT(*) FROM {}", visits.table),
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap_or_else(|e| {
+            log::warn!("Cannot count the visits of {:?}: {}", db_path, e);
+            (None, -1)
+        });
+    let max_visit_id = max_visit_id.unwrap_or_default();
+
+    // Nothing was deleted if every visit beyond the mark accounts for the growth
+    let since = match &mark {
+        Some(mark)
+            if mark.query == query
+                && !mark.recovered
+                && visit_count >= 0
+                && max_visit_id >= mark.max_visit_id =>
+        {
+            let added: Option<i64> = conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE id > ?", visits.table),
+                    [mark.max_visit_id],
+                    |row| row.get(0),
+                )
+                .ok();
+            (added == Some(visit_count - mark.visits)).then_some(mark)
+        }
+        _ => None,
+    };
+
+    // Entries with a visit beyond the mark cover every visit since
+    let mut pushdown = Pushdown::default();
+    if let Some(since) = since {
+        pushdown.push(&visits.newer_than(), Value::Integer(since.max_visit_id));
+    }
+    // Salvaged rows are narrowed down by the mark too, so they are added to
+    // the entries like any others rather than replacing them. The mark then
+    // records the damage, so the next change is read in full.
+    let (rows, recovered) = query_or_salvage(&conn, queries, &pushdown, read_entry)?;
+
+    match since {
+        Some(_) => log::debug!("Synced {} new entries of {:?}", rows.len(), db_path),
+        None => log::debug!("Synced all {} entries of {:?}", rows.len(), db_path),
+    }
+
+    let last_visit_time = rows
+        .iter()
+        .map(|entry| entry.3)
+        .chain(since.map(|mark| mark.last_visit_time))
+        .max()
+        .unwrap_or_default();
+
+    let tx = store.transaction()?;
+    if since.is_none() {
+        tx.execute("DELETE FROM entries WHERE source = ?", [source])?;
+        forget_missing(&tx)?;
+    }
+    {
+        let mut insert = tx.prepare(
+            "INSERT OR REPLACE INTO entries (source, url, title, visit_count, last_visit_time)
+             VALUES (?, ?, ?, ?, ?)",
+        )?;
+        for (url, title, visit_count, last_visit_time) in &rows {
+            insert.execute(params![source, url, title, visit_count, last_visit_time])?;
+        }
+    }
+    tx.execute(
+        "INSERT OR REPLACE INTO marks
+         (source, stamp, query, max_visit_id, visits, last_visit_time, recovered)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![
+            source,
+            stamp,
+            query,
+            max_visit_id,
+            visit_count,
+            last_visit_time,
+            recovered
+        ],
+    )?;
+    tx.commit()?;
+
+    Ok(recovered)
+}
+
+/// Drop the entries of databases that no longer exist, such as deleted profiles
+fn forget_missing(store: &Connection) -> SqliteResult<()> {
+    let sources = store
+        .prepare("SELECT source FROM marks")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<SqliteResult<Vec<_>>>()?;
+
+    for source in sources.iter().filter(|source| !Path::new(source).exists()) {
+        log::debug!("Forgetting the history of {:?}", source);
+        store.execute("DELETE FROM entries WHERE source = ?", [source])?;
+        store.execute("DELETE FROM marks WHERE source = ?", [source])?;
+    }
+    Ok(())
+}
+
+/// Read the mark of a database, if it was synced before
+fn load_mark(store: &Connection, source: &str) -> SqliteResult<Option<Mark>> {
+    store
+        .query_row(
+            "SELECT stamp, query, max_visit_id, visits, last_visit_time, recovered
+             FROM marks WHERE source = ?",
+            [source],
+            |row| {
+                Ok(Mark {
+                    stamp: row.get(0)?,
+                    query: row.get(1)?,
+                    max_visit_id: row.get(2)?,
+                    visits: row.get(3)?,
+                    last_visit_time: row.get(4)?,
+                    recovered: row.get(5)?,
+                })
+            },
+        )
+        .optional()
+}
+
+/// Read one entry of a history query, with its time in whole seconds
+fn read_entry(row: &Row<'_>) -> SqliteResult<(String, String, i64, i64)> {
+    let last_visit_time = match row.get::<_, Value>(3)? {
+        Value::Integer(time) => time,
+        Value::Real(time) => time as i64,
+        _ => 0,
+    };
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?, last_visit_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{QueryVariant, Salvage};
+    use std::io::{Seek, SeekFrom, Write};
+
+    const HISTORY: QuerySet = QuerySet {
+        variants: &[QueryVariant {
+            name: "test",
+            versions: 1..=1,
+            sql: "SELECT url, title, visit_count, last_visit_time FROM urls",
+        }],
+        minimal: "SELECT url, title, 0 AS visit_count, 0 AS last_visit_time FROM urls",
+        salvage: None,
+    };
+
+    const VISITS: Visits = Visits {
+        table: "visits",
+        entry: "url",
+        entries: "urls",
+    };
+
+    const SALVAGED_HISTORY: QuerySet = QuerySet {
+        salvage: Some(Salvage {
+            table: "urls",
+            columns: "url, title, visit_count, last_visit_time",
+        }),
+        ..HISTORY
+    };
+
+    /// A history database and a store it was synced into once
+    fn synced() -> (tempfile::TempDir, PathBuf, Connection) {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("History");
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE urls (id INTEGER PRIMARY KEY, url TEXT, title TEXT,
+                                    visit_count INTEGER, last_visit_time INTEGER);
+                 CREATE TABLE visits (id INTEGER PRIMARY KEY, url INTEGER);
+                 INSERT INTO urls VALUES (1, 'https://a.example', 'a', 1, 100),
+                                         (2, 'https://b.example', 'b', 1, 200);
+                 INSERT INTO visits VALUES (1, 1), (2, 2);",
+            )
+            .unwrap();
+
+        let mut store = Connection::open_in_memory().unwrap();
+        create_tables(&store).unwrap();
+        sync_again(&mut store, &db_path);
+        (dir, db_path, store)
+    }
+
+    /// Sync, forgetting the stamp so file times need not have moved
+    fn sync_again(store: &mut Connection, db_path: &Path) {
+        store.execute("UPDATE marks SET stamp = ''", []).unwrap();
+        let source = db_path.to_string_lossy();
+        sync(store, &source, db_path, Some(false), &HISTORY, &VISITS).unwrap();
+    }
+
+    fn entries(store: &Connection) -> Vec<(String, String)> {
+        store
+            .prepare("SELECT url, title FROM entries ORDER BY url")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn edit(db_path: &Path, sql: &str) {
+        Connection::open(db_path)
+            .unwrap()
+            .execute_batch(sql)
+            .unwrap();
+    }
+
+    #[test]
+    fn new_visits_are_read_from_the_mark() {
+        let (_dir, db_path, mut store) = synced();
+
+        // An entry older than the mark is not read again, so its new title
+        // only shows once it is visited
+        edit(
+            &db_path,
+            "UPDATE urls SET title = 'renamed' WHERE id = 1;
+             INSERT INTO urls VALUES (3, 'https://c.example', 'c', 1, 300);
+             INSERT INTO visits VALUES (3, 3);",
+        );
+        sync_again(&mut store, &db_path);
+
+        assert_eq!(
+            entries(&store),
+            [
+                ("https://a.example".into(), "a".into()),
+                ("https://b.example".into(), "b".into()),
+                ("https://c.example".into(), "c".into()),
+            ]
+        );
+        let mark = load_mark(&store, &db_path.to_string_lossy())
+            .unwrap()
+            .unwrap();
+        assert_eq!((mark.max_visit_id, mark.visits), (3, 3));
+        assert_eq!(mark.last_visit_time, 300);
+    }
+
+    #[test]
+    fn visits_older_than_the_mark_are_read_by_id() {
+        let (_dir, db_path, mut store) = synced();
+
+        // A visit synced from another device keeps the time it was made
+        edit(
+            &db_path,
+            "INSERT INTO urls VALUES (3, 'https://c.example', 'c', 1, 50);
+             INSERT INTO visits VALUES (3, 3);",
+        );
+        sync_again(&mut store, &db_path);
+
+        assert_eq!(
+            entries(&store),
+            [
+                ("https://a.example".into(), "a".into()),
+                ("https://b.example".into(), "b".into()),
+                ("https://c.example".into(), "c".into()),
+            ]
+        );
+        let mark = load_mark(&store, &db_path.to_string_lossy())
+            .unwrap()
+            .unwrap();
+        assert_eq!((mark.max_visit_id, mark.visits), (3, 3));
+        assert_eq!(mark.last_visit_time, 200);
+    }
+
+    #[test]
+    fn deleted_visits_read_everything_again() {
+        let (_dir, db_path, mut store) = synced();
+
+        // Clearing a visit below the mark while adding another keeps the
+        // highest id growing, but the counts no longer add up
+        edit(
+            &db_path,
+            "DELETE FROM visits WHERE id = 1;
+             DELETE FROM urls WHERE id = 1;
+             UPDATE urls SET title = 'renamed' WHERE id = 2;
+             INSERT INTO urls VALUES (3, 'https://c.example', 'c', 1, 300);
+             INSERT INTO visits VALUES (3, 3);",
+        );
+        sync_again(&mut store, &db_path);
+
+        assert_eq!(
+            entries(&store),
+            [
+                ("https://b.example".into(), "renamed".into()),
+                ("https://c.example".into(), "c".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn salvaged_new_visits_keep_the_synced_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("History");
+        edit(
+            &db_path,
+            "PRAGMA page_size = 4096;
+             CREATE TABLE urls (id INTEGER PRIMARY KEY, url TEXT, title TEXT,
+                                visit_count INTEGER, last_visit_time INTEGER);
+             CREATE TABLE visits (id INTEGER PRIMARY KEY, url INTEGER);
+             WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+             INSERT INTO urls SELECT i, 'https://example.com/' || i,
+                                     printf('%.100c', 't'), 1, i FROM n;
+             INSERT INTO visits SELECT id, id FROM urls;",
+        );
+        let mut store = Connection::open_in_memory().unwrap();
+        create_tables(&store).unwrap();
+        sync_again(&mut store, &db_path);
+        assert_eq!(entries(&store).len(), 2000);
+
+        // Overwrite a leaf page in the middle of urls, so the narrowed query
+        // fails and the salvage has to walk past the damage
+        edit(
+            &db_path,
+            "INSERT INTO urls VALUES (2001, 'https://example.com/new', 'new', 1, 3000);
+             INSERT INTO visits VALUES (2001, 2001);",
+        );
+        let mut file = fs::OpenOptions::new().write(true).open(&db_path).unwrap();
+        file.seek(SeekFrom::Start(19 * 4096)).unwrap();
+        file.write_all(&[0xff; 4096]).unwrap();
+        drop(file);
+
+        store.execute("UPDATE marks SET stamp = ''", []).unwrap();
+        let source = db_path.to_string_lossy();
+        let recovered = sync(
+            &mut store,
+            &source,
+            &db_path,
+            Some(false),
+            &SALVAGED_HISTORY,
+            &VISITS,
+        )
+        .unwrap();
+
+        assert!(recovered);
+        let entries = entries(&store);
+        assert_eq!(entries.len(), 2001);
+        assert!(entries.iter().any(|(_, title)| title == "new"));
+    }
+}
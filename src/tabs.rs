@@ -4,13 +4,18 @@
 //! - List tabs from various browsers (Chrome/Chromium, Safari, Firefox, Arc)
 //! - Focus on specific tabs in those browsers
 //! - Convert between different browser-specific tab formats
+//! - `search` listing the matching tabs of every source for the `tabs`
+//!   command, as `TabTarget`s the `focus` command takes back through `focus`
 
 use osascript::JavaScript;
 use serde::{Deserialize, Serialize};
 use std::error::Error as StdError;
 use std::fmt;
 
-use crate::browser::Browser;
+use crate::browser::{Browser, Engine};
+use crate::config;
+use crate::query::Query;
+use crate::source::sources;
 
 /// Scary browser errors
 #[derive(Debug)]
@@ -152,6 +157,15 @@ fn list_tabs(browser: &Browser) -> Result<Vec<Tab>, TabError> {
     Ok(tabs)
 }
 
+/// Returns true if a tab script can list the browser's tabs.
+///
+/// The scripts run through `osascript`, so only on macOS.
+pub fn has_tab_script(browser: &Browser) -> bool {
+    cfg!(target_os = "macos")
+        && (browser.engine() == Engine::Chromium
+            || matches!(browser, Browser::Safari | Browser::Zen))
+}
+
 pub fn focus_tab(browser: &Browser, tab: &Tab) -> Result<(), TabError> {
     // Choose the appropriate script based on browser type
     let script_content = match browser {
@@ -180,6 +194,7 @@ pub fn focus_tab(browser: &Browser, tab: &Tab) -> Result<(), TabError> {
 
 pub fn search_tabs(browser: &Browser, query: &str) -> Result<Vec<Tab>, TabError> {
     let tabs = list_tabs(browser)?;
+    let query = query.to_lowercase();
 
     let matching_tabs = tabs
         .into_iter()
@@ -190,3 +205,42 @@ pub fn search_tabs(browser: &Browser, query: &str) -> Result<Vec<Tab>, TabError>
 
     Ok(matching_tabs)
 }
+
+/// A tab found by `search`, passed to the `focus` command as JSON
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TabTarget {
+    /// Display name of the browser, see `Browser::name`
+    pub browser: String,
+    pub tab: Tab,
+}
+
+/// Search the open tabs of every source able to list them
+pub fn search(query: &Query) -> Vec<TabTarget> {
+    let mut targets = Vec::new();
+    for source in sources() {
+        if !source.capabilities().tabs {
+            continue;
+        }
+        match source.tabs(query) {
+            Ok(tabs) => targets.extend(tabs.into_iter().map(|tab| TabTarget {
+                browser: source.name().to_string(),
+                tab,
+            })),
+            Err(e) => log::debug!("Cannot list the tabs of {}: {}", source.name(), e),
+        }
+    }
+    targets
+}
+
+/// Focus the tab of a `TabTarget` given as JSON
+pub fn focus(target: &str) -> Result<(), Box<dyn StdError>> {
+    let target: TabTarget = serde_json::from_str(target)?;
+    let customs = (0..config::custom_browsers().len()).map(Browser::Custom);
+    let browser = Browser::BUILT_IN
+        .into_iter()
+        .chain(customs)
+        .find(|browser| browser.name() == target.browser)
+        .ok_or_else(|| TabError::Other(format!("Unknown browser {:?}", target.browser)))?;
+
+    Ok(focus_tab(&browser, &target.tab)?)
+}
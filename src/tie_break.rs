@@ -1,5 +1,5 @@
-use crate::search::{filter_results, SearchResult};
-use jiff::{SpanRound, Timestamp, Unit};
+use crate::search::SearchResult;
+use jiff::Timestamp;
 use nucleo::{self, Matcher};
 use std::cmp::Reverse;
 
@@ -9,14 +9,15 @@ pub fn break_a_tie(mut participants: Vec<SearchResult>, _matcher: &Matcher) -> V
 
     // Sort in‐place by descending freshness
     participants.sort_by_key(|participant| {
-        // Compute time since last visit (clamped to ≥ 0)
-        let days_since = now - participant.last_visit.unwrap();
+        // Compute time since last visit (clamped to ≥ 0); an unknown last
+        // visit counts as none
+        let days_since = participant.last_visit.map(|last_visit| now - last_visit);
 
         // We're operating on hours, not seconds or miliseconds, becuase it's more authentic to the browsing experience. People don't think of their sessions as activities that happen over the course of seconds, but rather hours or days. If freshness was too precise, it might actually conflict with memory.
-        let days: u32 = days_since.get_hours() as u32;
+        let days: u32 = days_since.map_or(0, |span| span.get_hours() as u32);
 
         // How many times they’ve visited
-        let visits: u32 = participant.visit_count.unwrap();
+        let visits: u32 = participant.visit_count.unwrap_or(0);
 
         // freshness = days × visits; Reverse to get descending order
         Reverse(days * visits)